@@ -72,7 +72,7 @@ impl ProofHandler for PoseidonProver {
     /// # Arguments
     ///
     /// * `input` - An `Input` struct containing:
-    ///   - `private_input`: A `Vec<u64>` representing the private part of the input to the hash function.
+    ///   - `private_input`: A `Vec<u64>` of at most [`test_circuit::MAX_INPUT_LEN`] elements representing the private part of the input to the hash function.
     ///   - `public_input`: A `String` representing the expected hash output in the field `Fp`.
     ///
     /// # Returns
@@ -88,51 +88,80 @@ impl ProofHandler for PoseidonProver {
         let params = ParamsKZG::<Bn256>::setup(K, OsRng);
 
         let private_inputs = input.private_input();
-        let circuit = test_circuit::TestCircuit::new(private_inputs);
-
-        let vk = keygen_vk(&params, &circuit).map_err(Error::while_keygen_vk)?;
-        let pk = keygen_pk(&params, vk, &circuit).map_err(Error::while_keygen_pk)?;
-
-        let out_hash = input.public_input()?;
-        let public_inputs: &[&[Fr]] = &[&[out_hash]];
-
-        // Initialize the proof transcript with a Blake2b hash function.
-        let mut proof_transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
-
-        // Create the zk-SNARK proof for the circuit and public inputs.
-        create_proof::<KZGCommitmentScheme<_>, ProverGWC<'_, _>, _, _, _, _>(
-            &params,
-            &pk,
-            &[circuit],
-            &[public_inputs],
-            OsRng,
-            &mut proof_transcript,
-        )
-        .map_err(Error::while_prove)?;
-        let proof = proof_transcript.finalize();
-
-        // Verify the proof to ensure its correctness before sending it off.
-        let mut verify_transcript = Blake2bRead::<_, _, Challenge255<_>>::init(&proof[..]);
-        let strategy = SingleStrategy::new(&params);
-        verify_proof::<
-            KZGCommitmentScheme<Bn256>,
-            VerifierGWC<'_, Bn256>,
-            Challenge255<G1Affine>,
-            Blake2bRead<&[u8], G1Affine, Challenge255<G1Affine>>,
-            SingleStrategy<'_, Bn256>,
-        >(
-            &params,
-            pk.get_vk(),
-            strategy,
-            &[public_inputs],
-            &mut verify_transcript,
-        )
-        .map_err(Error::while_verify)?;
+        // The layout of the circuit is fixed by the number of inputs.
+        let proof = match private_inputs.len() {
+            0 => prove_len::<0>(&params, &private_inputs, &input),
+            1 => prove_len::<1>(&params, &private_inputs, &input),
+            2 => prove_len::<2>(&params, &private_inputs, &input),
+            3 => prove_len::<3>(&params, &private_inputs, &input),
+            4 => prove_len::<4>(&params, &private_inputs, &input),
+            5 => prove_len::<5>(&params, &private_inputs, &input),
+            6 => prove_len::<6>(&params, &private_inputs, &input),
+            7 => prove_len::<7>(&params, &private_inputs, &input),
+            8 => prove_len::<8>(&params, &private_inputs, &input),
+            actual => Err(Error::InvalidInputLength {
+                max: test_circuit::MAX_INPUT_LEN,
+                actual,
+            }),
+        }?;
 
         Ok(BS64.encode(proof))
     }
 }
 
+/// Proves and verifies the hash of `L` private inputs, see [`PoseidonProver::prove`]
+fn prove_len<const L: usize>(
+    params: &ParamsKZG<Bn256>,
+    private_inputs: &[Fr],
+    input: &Input,
+) -> Result<Vec<u8>, Error> {
+    let private_inputs = private_inputs
+        .try_into()
+        .expect("L is the number of private inputs");
+    let circuit = test_circuit::TestCircuit::<_, L>::new(private_inputs);
+
+    let vk = keygen_vk(params, &circuit).map_err(Error::while_keygen_vk)?;
+    let pk = keygen_pk(params, vk, &circuit).map_err(Error::while_keygen_pk)?;
+
+    let out_hash = input.public_input()?;
+    let public_inputs: &[&[Fr]] = &[&[out_hash]];
+
+    // Initialize the proof transcript with a Blake2b hash function.
+    let mut proof_transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+
+    // Create the zk-SNARK proof for the circuit and public inputs.
+    create_proof::<KZGCommitmentScheme<_>, ProverGWC<'_, _>, _, _, _, _>(
+        params,
+        &pk,
+        &[circuit],
+        &[public_inputs],
+        OsRng,
+        &mut proof_transcript,
+    )
+    .map_err(Error::while_prove)?;
+    let proof = proof_transcript.finalize();
+
+    // Verify the proof to ensure its correctness before sending it off.
+    let mut verify_transcript = Blake2bRead::<_, _, Challenge255<_>>::init(&proof[..]);
+    let strategy = SingleStrategy::new(params);
+    verify_proof::<
+        KZGCommitmentScheme<Bn256>,
+        VerifierGWC<'_, Bn256>,
+        Challenge255<G1Affine>,
+        Blake2bRead<&[u8], G1Affine, Challenge255<G1Affine>>,
+        SingleStrategy<'_, Bn256>,
+    >(
+        params,
+        pk.get_vk(),
+        strategy,
+        &[public_inputs],
+        &mut verify_transcript,
+    )
+    .map_err(Error::while_verify)?;
+
+    Ok(proof)
+}
+
 /// Enumerates the potential errors that can occur within the [`PoseidonProver`].
 ///
 /// This error enum captures the various points of failure that could occur
@@ -147,6 +176,7 @@ pub enum Error {
     WhileKeygenVk { plonk_error: String },
     WhileKeygenPk { plonk_error: String },
     PubInputOutOfField { public_input: String },
+    InvalidInputLength { max: usize, actual: usize },
     WhileProve { plonk_error: String },
     WhileVerify { plonk_error: String },
 }
@@ -0,0 +1,144 @@
+use halo2_proofs::arithmetic::CurveAffine;
+use halo2curves::group::ff::{FromUniformBytes, PrimeField};
+
+use crate::poseidon_hash::{MerkleTree, PoseidonHash, PoseidonSpec};
+
+/// Append-only Merkle tree of fixed `depth` keeping only its frontier and the history of its
+/// roots, the leaves are appended from the left and the missing ones are zero
+///
+/// Nodes are hashed as in [`MerkleTree`], so that the root is the one of the [`MerkleTree`] of
+/// the appended leaves padded with zeros. See
+/// [`crate::merkle_circuit::IncrementalMerkleChip`] for the in-circuit append.
+#[derive(Clone, Debug)]
+pub struct IncrementalMerkleTree<
+    C: CurveAffine<ScalarExt = F>,
+    F: PrimeField + FromUniformBytes<64>,
+> {
+    hasher: PoseidonHash<C, F, 3, 2>,
+    // `empty[level]` is the root of an empty subtree of height `level`
+    empty: Vec<F>,
+    // `frontier[level]` is the last left child appended at `level`, only meaningful when the
+    // bit `level` of `len` is set
+    frontier: Vec<F>,
+    len: u64,
+    // the root after each append, the root of the empty tree first
+    roots: Vec<F>,
+}
+
+impl<C: CurveAffine<ScalarExt = F>, F: PrimeField + FromUniformBytes<64>>
+    IncrementalMerkleTree<C, F>
+{
+    pub fn new(spec: PoseidonSpec<F, 3, 2>, depth: usize) -> Self {
+        assert!(depth > 0 && depth < 64);
+        let hasher = MerkleTree::<C, F>::hasher(spec);
+        let mut empty = vec![F::ZERO];
+        for _ in 0..depth {
+            let node = *empty.last().unwrap();
+            empty.push(hasher.compress([node, node]));
+        }
+        Self {
+            hasher,
+            frontier: empty[..depth].to_vec(),
+            roots: vec![empty[depth]],
+            empty,
+            len: 0,
+        }
+    }
+
+    /// Number of levels above the leaves
+    pub fn depth(&self) -> usize {
+        self.frontier.len()
+    }
+
+    /// Number of appended leaves, i.e. the index of the next one
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn root(&self) -> F {
+        *self.roots.last().unwrap()
+    }
+
+    /// Every root of the tree, from the empty tree to the current one
+    pub fn roots(&self) -> &[F] {
+        &self.roots
+    }
+
+    /// Whether `root` has been the root of this tree at some point
+    pub fn is_known_root(&self, root: &F) -> bool {
+        self.roots.contains(root)
+    }
+
+    /// The frontier to append the next leaf from, see [`IncrementalMerkleTree`]
+    pub fn frontier(&self) -> &[F] {
+        &self.frontier
+    }
+
+    /// Appends `leaf` and returns its index
+    pub fn append(&mut self, leaf: F) -> u64 {
+        let index = self.len;
+        assert!(index < 1 << self.depth(), "the tree is full");
+        let mut node = leaf;
+        for level in 0..self.depth() {
+            node = if (index >> level) & 1 == 1 {
+                self.hasher.compress([self.frontier[level], node])
+            } else {
+                self.frontier[level] = node;
+                self.hasher.compress([node, self.empty[level]])
+            };
+        }
+        self.len += 1;
+        self.roots.push(node);
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::pasta::{EqAffine, Fp};
+
+    use super::*;
+
+    const R_F: usize = 8;
+    const R_P: usize = 57;
+
+    #[test]
+    fn test_incremental_merkle_tree() {
+        let spec = PoseidonSpec::<Fp, 3, 2>::new(R_F, R_P);
+        let mut tree = IncrementalMerkleTree::<EqAffine, Fp>::new(spec.clone(), 3);
+        let mut leaves = vec![Fp::from(0u64); 8];
+        assert!(tree.is_empty());
+        assert_eq!(
+            tree.root(),
+            MerkleTree::<EqAffine, Fp>::new(spec.clone(), leaves.clone()).root()
+        );
+
+        for i in 0..8u64 {
+            let leaf = Fp::from(i + 10);
+            leaves[i as usize] = leaf;
+            assert_eq!(tree.append(leaf), i);
+            assert_eq!(
+                tree.root(),
+                MerkleTree::<EqAffine, Fp>::new(spec.clone(), leaves.clone()).root()
+            );
+        }
+        assert_eq!(tree.len(), 8);
+        assert_eq!(tree.roots().len(), 9);
+        assert!(tree.is_known_root(&tree.roots()[3]));
+        assert!(!tree.is_known_root(&Fp::from(1u64)));
+    }
+
+    #[test]
+    #[should_panic(expected = "the tree is full")]
+    fn test_incremental_merkle_tree_full() {
+        let spec = PoseidonSpec::<Fp, 3, 2>::new(R_F, R_P);
+        let mut tree = IncrementalMerkleTree::<EqAffine, Fp>::new(spec, 1);
+        for i in 0..3 {
+            tree.append(Fp::from(i as u64));
+        }
+    }
+}
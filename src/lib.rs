@@ -2,8 +2,16 @@ pub use ff;
 pub use halo2_proofs;
 pub use halo2curves;
 
+pub mod incremental_merkle_tree;
 pub mod main_gate;
+pub mod merkle_circuit;
+pub mod poseidon2_circuit;
+pub mod poseidon2_hash;
 pub mod poseidon_circuit;
 pub mod poseidon_hash;
 pub mod ro_types;
+pub mod round_gate;
+pub mod sparse_merkle_tree;
 pub mod test_circuit;
+pub mod transcript;
+pub mod transcript_circuit;
@@ -13,20 +13,25 @@ use halo2_proofs::{
 use halo2curves::bn256::{Bn256, Fr, G1Affine};
 use rand_core::OsRng;
 
+pub mod incremental_merkle_tree;
 pub mod main_gate;
+pub mod merkle_circuit;
+pub mod poseidon2_circuit;
+pub mod poseidon2_hash;
 pub mod poseidon_circuit;
 pub mod poseidon_hash;
 pub mod ro_types;
+pub mod round_gate;
+pub mod sparse_merkle_tree;
 pub mod test_circuit;
+pub mod transcript;
+pub mod transcript_circuit;
 
 fn main() {
     println!("-----running Poseidon Circuit-----");
     const K: u32 = 10;
     let params = ParamsKZG::<Bn256>::setup(K, OsRng);
-    let mut inputs = Vec::new();
-    for i in 0..5 {
-        inputs.push(Fr::from(i as u64));
-    }
+    let inputs: [Fr; 5] = std::array::from_fn(|i| Fr::from(i as u64));
     let circuit = test_circuit::TestCircuit::new(inputs);
 
     let vk = keygen_vk(&params, &circuit).expect("keygen_vk should not fail");
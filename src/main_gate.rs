@@ -6,6 +6,7 @@ use halo2_proofs::{
     plonk::{Advice, Column, ConstraintSystem, Error, Expression, Fixed},
     poly::Rotation,
 };
+use serde::{Deserialize, Serialize};
 
 pub type AssignedValue<F> = AssignedCell<F, F>;
 
@@ -90,6 +91,125 @@ impl<F: PrimeField> From<&AssignedValue<F>> for WrapValue<F> {
     }
 }
 
+/// The S-box `x^alpha` of the `q_5` term, a permutation of `F` only when
+/// `gcd(alpha, p - 1) = 1`. The degree of the gates grows with `alpha`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SBox {
+    Pow3,
+    #[default]
+    Pow5,
+    Pow7,
+    Pow17,
+}
+
+impl SBox {
+    pub fn alpha(&self) -> u64 {
+        match self {
+            SBox::Pow3 => 3,
+            SBox::Pow5 => 5,
+            SBox::Pow7 => 7,
+            SBox::Pow17 => 17,
+        }
+    }
+
+    /// Whether `x^alpha` is a permutation of `F`, i.e. whether the prime `alpha` does not
+    /// divide `p - 1`
+    pub fn is_valid<F: PrimeField>(&self) -> bool {
+        let alpha = self.alpha();
+        let rem = (-F::ONE)
+            .to_repr()
+            .as_ref()
+            .iter()
+            .rev()
+            .fold(0, |rem, byte| ((rem << 8) + *byte as u64) % alpha);
+        rem != 0
+    }
+
+    /// Panics unless the S-box [`Self::is_valid`] for `F`
+    pub fn validate<F: PrimeField>(&self) {
+        assert!(
+            self.is_valid::<F>(),
+            "x^{} is not a permutation of the field",
+            self.alpha()
+        );
+    }
+
+    /// Whether `r_f` full and `r_p` partial rounds of width `t` meet the bounds of the
+    /// [Poseidon paper](https://eprint.iacr.org/2019/458.pdf) for 128 bits of security with
+    /// this S-box, as computed by the round numbers script of its reference implementation,
+    /// i.e. with the attack of [eprint 2023/537](https://eprint.iacr.org/2023/537.pdf) but
+    /// without the security margin added on top of the minimal rounds
+    pub fn rounds_are_secure<F: PrimeField>(&self, t: usize, r_f: usize, r_p: usize) -> bool {
+        const M: f64 = 128.0;
+        let alpha = self.alpha() as f64;
+        let log_alpha = alpha.log2();
+        // `floor(log2(p))`
+        let log_p = (F::NUM_BITS - 1) as f64;
+        let (t, r_f, r_p, r) = (t as f64, r_f as f64, r_p as f64, (t / 3) as f64);
+
+        let statistical = if M <= (log_p - (alpha - 1.0) / 2.0) * (t + 1.0) {
+            6.0
+        } else {
+            10.0
+        };
+        let interpolation =
+            1.0 + (M.min(log_p) / log_alpha).ceil() + (t.log2() / log_alpha).ceil() - r_p;
+        let groebner_1 = M.min(log_p) / log_alpha - r_p;
+        let groebner_2 = t - 1.0 + (M / (t + 1.0)).min(log_p / 2.0) / log_alpha - r_p;
+        let groebner_3 = (t - 2.0 + M / (2.0 * log_alpha) - r_p) / (t - 1.0);
+        let min_r_f = [
+            statistical,
+            interpolation,
+            groebner_1,
+            groebner_2,
+            groebner_3,
+        ]
+        .into_iter()
+        .map(f64::ceil)
+        .fold(0.0, f64::max);
+
+        // the attack of 2023/537 costs `binomial(over, under)^2`
+        let under = r * r_f / 2.0 + r_p + alpha;
+        let over = (r_f - 1.0) * t + r_p + r + under;
+        let log_binomial = (1..=under as u64)
+            .map(|i| ((over - under + i as f64) / i as f64).log2())
+            .sum::<f64>();
+        r_f >= min_r_f && (2.0 * log_binomial).ceil() >= M
+    }
+
+    /// Panics unless the rounds [`Self::rounds_are_secure`]
+    pub fn validate_rounds<F: PrimeField>(&self, t: usize, r_f: usize, r_p: usize) {
+        assert!(
+            self.rounds_are_secure::<F>(t, r_f, r_p),
+            "{r_f} full and {r_p} partial rounds are not enough for x^{}",
+            self.alpha()
+        );
+    }
+
+    pub fn apply<F: PrimeField>(&self, v: F) -> F {
+        v.pow_vartime([self.alpha()])
+    }
+
+    pub fn apply_value<F: PrimeField>(&self, v: Value<F>) -> Value<F> {
+        v.map(|v| self.apply(v))
+    }
+
+    /// `v^alpha` by square-and-multiply over the bits of `alpha`
+    pub fn expression<F: PrimeField>(&self, v: Expression<F>) -> Expression<F> {
+        let alpha = self.alpha();
+        (0..63 - alpha.leading_zeros())
+            .rev()
+            .fold(v.clone(), |acc, i| {
+                let acc = acc.clone() * acc;
+                if (alpha >> i) & 1 == 1 {
+                    acc * v.clone()
+                } else {
+                    acc
+                }
+            })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct MainGateConfig<const T: usize> {
     pub(crate) state: [Column<Advice>; T],
@@ -103,6 +223,13 @@ pub struct MainGateConfig<const T: usize> {
     pub(crate) q_i: Column<Fixed>,
     pub(crate) q_o: Column<Fixed>,
     pub(crate) rc: Column<Fixed>,
+    pub(crate) sbox: SBox,
+}
+
+impl<const T: usize> MainGateConfig<T> {
+    pub fn sbox(&self) -> SBox {
+        self.sbox
+    }
 }
 
 #[derive(Debug)]
@@ -136,8 +263,20 @@ impl<F: PrimeField, const T: usize> MainGate<F, T> {
         meta: &mut ConstraintSystem<F>,
         adv_cols: &mut (impl Iterator<Item = Column<Advice>> + Clone),
         fix_cols: &mut (impl Iterator<Item = Column<Fixed>> + Clone),
+    ) -> MainGateConfig<T> {
+        Self::configure_with_sbox(meta, adv_cols, fix_cols, SBox::default())
+    }
+
+    /// Configures the gate with `sbox` in place of `x^5` in the `q_5` term, it panics unless
+    /// `sbox` is a permutation of `F`
+    pub fn configure_with_sbox(
+        meta: &mut ConstraintSystem<F>,
+        adv_cols: &mut (impl Iterator<Item = Column<Advice>> + Clone),
+        fix_cols: &mut (impl Iterator<Item = Column<Fixed>> + Clone),
+        sbox: SBox,
     ) -> MainGateConfig<T> {
         assert!(T >= 2);
+        sbox.validate::<F>();
         let state = [0; T].map(|_| adv_cols.next().unwrap());
         let input = adv_cols.next().unwrap();
         let out = adv_cols.next().unwrap();
@@ -154,12 +293,7 @@ impl<F: PrimeField, const T: usize> MainGate<F, T> {
         meta.enable_equality(input);
         meta.enable_equality(out);
 
-        let pow_5 = |v: Expression<F>| {
-            let v2 = v.clone() * v.clone();
-            v2.clone() * v2 * v
-        };
-
-        meta.create_gate("q_m*s[0]*s[1] + sum_i(q_1[i]*s[i]) + sum_i(q_5[i]*s[i]^5) + rc + q_i*input + q_o*out=0", |meta|{
+        meta.create_gate("q_m*s[0]*s[1] + sum_i(q_1[i]*s[i]) + sum_i(q_5[i]*sbox(s[i])) + rc + q_i*input + q_o*out=0", |meta|{
             let state = state.into_iter().map(|s| meta.query_advice(s, Rotation::cur())).collect::<Vec<_>>();
             let input = meta.query_advice(input, Rotation::cur());
             let out = meta.query_advice(out, Rotation::cur());
@@ -171,7 +305,7 @@ impl<F: PrimeField, const T: usize> MainGate<F, T> {
             let rc = meta.query_fixed(rc, Rotation::cur());
             let init_term = q_m * state[0].clone() * state[1].clone() + q_i * input + rc + q_o * out;
             let res = state.into_iter().zip(q_1).zip(q_5).map(|((s, q1), q5)| {
-                q1 * s.clone()  +  q5 * pow_5(s)
+                q1 * s.clone()  +  q5 * sbox.expression(s)
             }).fold(init_term, |acc, item| {
                 acc + item
             });
@@ -188,6 +322,7 @@ impl<F: PrimeField, const T: usize> MainGate<F, T> {
             q_i,
             q_o,
             rc,
+            sbox,
         }
     }
 
@@ -248,4 +383,1092 @@ impl<F: PrimeField, const T: usize> MainGate<F, T> {
         ctx.next();
         Ok(res)
     }
+
+    /// Assigns a value constrained to be boolean, in the `input` column of the row of
+    /// [`Self::assert_bool`] which leaves it unused
+    pub fn assign_bit(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        bit: Value<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        let bit = ctx.assign_advice(|| "bit: input", self.config.input, bit)?;
+        self.assert_bool(ctx, &bit)?;
+        Ok(bit)
+    }
+
+    /// Assigns `a + b`
+    pub fn add(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.linear_combination(ctx, &[(F::ONE, a.clone()), (F::ONE, b.clone())], F::ZERO)
+    }
+
+    /// Assigns `a - b`
+    pub fn sub(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.linear_combination(ctx, &[(F::ONE, a.clone()), (-F::ONE, b.clone())], F::ZERO)
+    }
+
+    /// Assigns `a + constant`
+    pub fn add_constant(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        constant: F,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.linear_combination(ctx, &[(F::ONE, a.clone())], constant)
+    }
+
+    /// Assigns `a * b`
+    pub fn mul(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.mul_add_inner(ctx, a, b, None)
+    }
+
+    /// Assigns `a * b + c`
+    pub fn mul_add(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+        c: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.mul_add_inner(ctx, a, b, Some(c))
+    }
+
+    /// Assigns `a^2`
+    pub fn square(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.mul(ctx, a, a)
+    }
+
+    /// Assigns `a^5` in a single row through the quintic term, or in three rows of
+    /// [`Self::square`] and [`Self::mul`] when the S-box of the gate is not `x^5`
+    pub fn pow5(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        if self.config.sbox == SBox::Pow5 {
+            return self.sbox(ctx, a);
+        }
+        let a2 = self.square(ctx, a)?;
+        let a4 = self.square(ctx, &a2)?;
+        self.mul(ctx, &a4, a)
+    }
+
+    /// Assigns `a^alpha` in a single row through the `q_5` term, see [`SBox`]
+    pub fn sbox(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        let s0 = ctx.assign_advice(|| "sbox: state", self.config.state[0], a.value().copied())?;
+        ctx.constrain_equal(s0.cell(), a.cell())?;
+        ctx.assign_fixed(|| "sbox: q_5", self.config.q_5[0], F::ONE)?;
+        ctx.assign_fixed(|| "sbox: q_o", self.config.q_o, -F::ONE)?;
+        let out = ctx.assign_advice(
+            || "sbox: out",
+            self.config.out,
+            self.config.sbox.apply_value(a.value().copied()),
+        )?;
+        ctx.next();
+        Ok(out)
+    }
+
+    // `s[0] = a`, `s[1] = b` and `input = c`, all copy-constrained
+    fn mul_add_inner(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+        c: Option<&AssignedValue<F>>,
+    ) -> Result<AssignedValue<F>, Error> {
+        let mut out_val = a.value().copied() * b.value().copied();
+        for (i, v) in [a, b].into_iter().enumerate() {
+            let si = ctx.assign_advice(
+                || "mul_add: state",
+                self.config.state[i],
+                v.value().copied(),
+            )?;
+            ctx.constrain_equal(si.cell(), v.cell())?;
+        }
+        ctx.assign_fixed(|| "mul_add: q_m", self.config.q_m, F::ONE)?;
+        if let Some(c) = c {
+            let input =
+                ctx.assign_advice(|| "mul_add: input", self.config.input, c.value().copied())?;
+            ctx.constrain_equal(input.cell(), c.cell())?;
+            ctx.assign_fixed(|| "mul_add: q_i", self.config.q_i, F::ONE)?;
+            out_val = out_val + c.value().copied();
+        }
+        ctx.assign_fixed(|| "mul_add: q_o", self.config.q_o, -F::ONE)?;
+        let out = ctx.assign_advice(|| "mul_add: out", self.config.out, out_val)?;
+        ctx.next();
+        Ok(out)
+    }
+
+    /// Assigns `sum_i(coeff_i * value_i) + constant`, `T` terms per row, the partial sum being
+    /// carried to the next row through the `input` column
+    pub fn linear_combination(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        terms: &[(F, AssignedValue<F>)],
+        constant: F,
+    ) -> Result<AssignedValue<F>, Error> {
+        assert!(!terms.is_empty());
+        let mut acc: Option<AssignedValue<F>> = None;
+        for chunk in terms.chunks(T) {
+            let mut acc_val = match &acc {
+                Some(acc) => acc.value().copied(),
+                None => Value::known(constant),
+            };
+            for (i, (coeff, value)) in chunk.iter().enumerate() {
+                let si = ctx.assign_advice(
+                    || "linear_combination: state",
+                    self.config.state[i],
+                    value.value().copied(),
+                )?;
+                ctx.constrain_equal(si.cell(), value.cell())?;
+                ctx.assign_fixed(|| "linear_combination: q_1", self.config.q_1[i], *coeff)?;
+                acc_val = acc_val + value.value().copied() * Value::known(*coeff);
+            }
+            match &acc {
+                Some(acc) => {
+                    let input = ctx.assign_advice(
+                        || "linear_combination: input",
+                        self.config.input,
+                        acc.value().copied(),
+                    )?;
+                    ctx.constrain_equal(input.cell(), acc.cell())?;
+                    ctx.assign_fixed(|| "linear_combination: q_i", self.config.q_i, F::ONE)?;
+                }
+                None => {
+                    ctx.assign_fixed(|| "linear_combination: rc", self.config.rc, constant)?;
+                }
+            }
+            ctx.assign_fixed(|| "linear_combination: q_o", self.config.q_o, -F::ONE)?;
+            acc =
+                Some(ctx.assign_advice(|| "linear_combination: out", self.config.out, acc_val)?);
+            ctx.next();
+        }
+        Ok(acc.expect("terms are not empty"))
+    }
+
+    /// Constrains `a` and `b` to be equal, with a copy constraint rather than a row
+    pub fn assert_equal(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<(), Error> {
+        ctx.constrain_equal(a.cell(), b.cell())
+    }
+
+    /// Constrains `value` to be zero
+    pub fn assert_zero(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        value: &AssignedValue<F>,
+    ) -> Result<(), Error> {
+        let s0 = ctx.assign_advice(
+            || "assert_zero: state",
+            self.config.state[0],
+            value.value().copied(),
+        )?;
+        ctx.constrain_equal(s0.cell(), value.cell())?;
+        ctx.assign_fixed(|| "assert_zero: q_1", self.config.q_1[0], F::ONE)?;
+        ctx.next();
+        Ok(())
+    }
+
+    /// Constrains `a` to be boolean by `s[0] * s[1] - s[0] = 0` with `s[0] = s[1] = a`
+    pub fn assert_bool(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+    ) -> Result<(), Error> {
+        for i in 0..2 {
+            let si = ctx.assign_advice(
+                || "assert_bool: state",
+                self.config.state[i],
+                a.value().copied(),
+            )?;
+            ctx.constrain_equal(si.cell(), a.cell())?;
+        }
+        ctx.assign_fixed(|| "assert_bool: q_m", self.config.q_m, F::ONE)?;
+        ctx.assign_fixed(|| "assert_bool: q_1", self.config.q_1[0], -F::ONE)?;
+        ctx.next();
+        Ok(())
+    }
+
+    /// Assigns `a AND b`, i.e. `a * b`, the booleanity of `a` and `b` being assumed here and
+    /// in the other boolean gadgets, see [`Self::assert_bool`]
+    pub fn and(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.mul(ctx, a, b)
+    }
+
+    /// Assigns `a OR b`, i.e. `a + b - a * b`
+    pub fn or(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.bilinear(ctx, a, b, -F::ONE, F::ONE)
+    }
+
+    /// Assigns `a XOR b`, i.e. `a + b - 2 * a * b`
+    pub fn xor(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.bilinear(ctx, a, b, -F::ONE.double(), F::ONE)
+    }
+
+    /// Assigns `NOT a`, i.e. `1 - a`
+    pub fn not(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.linear_combination(ctx, &[(-F::ONE, a.clone())], F::ONE)
+    }
+
+    // `q_m * a * b + q_1 * (a + b)` in a single row, with `s[0] = a` and `s[1] = b`
+    fn bilinear(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+        q_m: F,
+        q_1: F,
+    ) -> Result<AssignedValue<F>, Error> {
+        for (i, v) in [a, b].into_iter().enumerate() {
+            let si = ctx.assign_advice(
+                || "bilinear: state",
+                self.config.state[i],
+                v.value().copied(),
+            )?;
+            ctx.constrain_equal(si.cell(), v.cell())?;
+            ctx.assign_fixed(|| "bilinear: q_1", self.config.q_1[i], q_1)?;
+        }
+        ctx.assign_fixed(|| "bilinear: q_m", self.config.q_m, q_m)?;
+        ctx.assign_fixed(|| "bilinear: q_o", self.config.q_o, -F::ONE)?;
+        let (a, b) = (a.value().copied(), b.value().copied());
+        let out_val = a * b * Value::known(q_m) + (a + b) * Value::known(q_1);
+        let out = ctx.assign_advice(|| "bilinear: out", self.config.out, out_val)?;
+        ctx.next();
+        Ok(out)
+    }
+
+    /// Assigns `a` when `cond` is one and `b` when it is zero, `cond` being assumed boolean
+    pub fn select(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        cond: &AssignedValue<F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        // b + cond * (a - b)
+        let diff = self.sub(ctx, a, b)?;
+        self.mul_add(ctx, cond, &diff, b)
+    }
+
+    /// Assigns `(b, a)` when `cond` is one and `(a, b)` when it is zero, `cond` being assumed
+    /// boolean
+    pub fn conditional_swap(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        cond: &AssignedValue<F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<(AssignedValue<F>, AssignedValue<F>), Error> {
+        let first = self.select(ctx, cond, b, a)?;
+        let second = self.linear_combination(
+            ctx,
+            &[
+                (F::ONE, a.clone()),
+                (F::ONE, b.clone()),
+                (-F::ONE, first.clone()),
+            ],
+            F::ZERO,
+        )?;
+        Ok((first, second))
+    }
+
+    /// Assigns `1` when `a` is zero and `0` otherwise
+    ///
+    /// With the inverse of `a`, or zero, as witness, `a * inverse + out - 1 = 0` forces
+    /// `out = 1` when `a` is zero and `a * out = 0` forces `out = 0` otherwise.
+    pub fn is_zero(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        self.inverse_and_flag(ctx, a).map(|(_, flag)| flag)
+    }
+
+    // the inverse witness of `a`, or zero, and the flag of `Self::is_zero`
+    fn inverse_and_flag(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+    ) -> Result<(AssignedValue<F>, AssignedValue<F>), Error> {
+        let inverse_val = a.value().map(|a| a.invert().unwrap_or(F::ZERO));
+        let s0 = ctx.assign_advice(
+            || "is_zero: state",
+            self.config.state[0],
+            a.value().copied(),
+        )?;
+        ctx.constrain_equal(s0.cell(), a.cell())?;
+        let inverse =
+            ctx.assign_advice(|| "is_zero: inverse", self.config.state[1], inverse_val)?;
+        ctx.assign_fixed(|| "is_zero: q_m", self.config.q_m, F::ONE)?;
+        ctx.assign_fixed(|| "is_zero: rc", self.config.rc, -F::ONE)?;
+        ctx.assign_fixed(|| "is_zero: q_o", self.config.q_o, F::ONE)?;
+        let flag_val = Value::known(F::ONE) - a.value().copied() * inverse_val;
+        let flag = ctx.assign_advice(|| "is_zero: out", self.config.out, flag_val)?;
+        ctx.next();
+
+        self.assert_product_zero(ctx, a, &flag)?;
+        Ok((inverse, flag))
+    }
+
+    // constrains `a * b = 0` in a single row
+    fn assert_product_zero(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<(), Error> {
+        for (i, v) in [a, b].into_iter().enumerate() {
+            let si = ctx.assign_advice(
+                || "assert_product_zero: state",
+                self.config.state[i],
+                v.value().copied(),
+            )?;
+            ctx.constrain_equal(si.cell(), v.cell())?;
+        }
+        ctx.assign_fixed(|| "assert_product_zero: q_m", self.config.q_m, F::ONE)?;
+        ctx.next();
+        Ok(())
+    }
+
+    /// Assigns `1` when `a` and `b` are equal and `0` otherwise, see [`Self::is_zero`]
+    pub fn is_equal(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        let diff = self.sub(ctx, a, b)?;
+        self.is_zero(ctx, &diff)
+    }
+
+    /// Constrains `a` not to be zero, see [`Self::invert`]
+    pub fn assert_not_zero(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+    ) -> Result<(), Error> {
+        self.invert(ctx, a).map(|_| ())
+    }
+
+    /// Constrains `a` and `b` to differ, see [`Self::assert_not_zero`]
+    pub fn assert_not_equal(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<(), Error> {
+        let diff = self.sub(ctx, a, b)?;
+        self.assert_not_zero(ctx, &diff)
+    }
+
+    /// Assigns the inverse of `a` by `a * inverse - 1 = 0` in a single row, which constrains
+    /// `a` not to be zero as well
+    pub fn invert(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        let inverse_val = a.value().map(|a| a.invert().unwrap_or(F::ZERO));
+        let s0 = ctx.assign_advice(|| "invert: state", self.config.state[0], a.value().copied())?;
+        ctx.constrain_equal(s0.cell(), a.cell())?;
+        let inverse = ctx.assign_advice(|| "invert: inverse", self.config.state[1], inverse_val)?;
+        ctx.assign_fixed(|| "invert: q_m", self.config.q_m, F::ONE)?;
+        ctx.assign_fixed(|| "invert: rc", self.config.rc, -F::ONE)?;
+        ctx.next();
+        Ok(inverse)
+    }
+
+    /// Assigns the inverse of `a`, or zero when `a` is zero, along with the flag of
+    /// [`Self::is_zero`]
+    ///
+    /// On top of the constraints of [`Self::is_zero`], `inverse * flag = 0` forces the
+    /// inverse to be zero when `a` is.
+    pub fn invert_or_zero(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+    ) -> Result<(AssignedValue<F>, AssignedValue<F>), Error> {
+        let (inverse, flag) = self.inverse_and_flag(ctx, a)?;
+        self.assert_product_zero(ctx, &inverse, &flag)?;
+        Ok((inverse, flag))
+    }
+
+    /// Assigns `a / b`, `b` being constrained not to be zero, see [`Self::invert`]
+    pub fn div(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        a: &AssignedValue<F>,
+        b: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        let inverse = self.invert(ctx, b)?;
+        self.mul(ctx, a, &inverse)
+    }
+
+    /// Recomposes little-endian `bits` into a value, `T` bits per row
+    ///
+    /// The booleanity of `bits` is not constrained here, see [`Self::to_bits`]
+    pub fn from_bits(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        bits: &[AssignedValue<F>],
+    ) -> Result<AssignedValue<F>, Error> {
+        let mut coeff = F::ONE;
+        let terms = bits
+            .iter()
+            .map(|bit| {
+                let term = (coeff, bit.clone());
+                coeff = coeff.double();
+                term
+            })
+            .collect::<Vec<_>>();
+        self.linear_combination(ctx, &terms, F::ZERO)
+    }
+
+    /// Decomposes `value` into `num_bits` little-endian bits: every bit is constrained
+    /// to be boolean, a row each, and the bits are recomposed into `value`, `T` per row
+    ///
+    /// The booleanity of several bits can't be packed in a row: a row is a single equation
+    /// whose only products are `q_m * s[0] * s[1]` and the S-box terms, so it can't imply
+    /// `b * b - b = 0` for more than one bit. `b^alpha = b` on a lane isn't enough either,
+    /// as every `(alpha - 1)`-th root of unity satisfies it. Packing would need a product
+    /// term per lane, i.e. another gate.
+    ///
+    /// With `num_bits < F::NUM_BITS` this range checks `value` as well, with
+    /// `num_bits == F::NUM_BITS` the decomposition is not guaranteed to be canonical, see
+    /// [`Self::to_bits_canonical`]
+    pub fn to_bits(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        value: &AssignedValue<F>,
+        num_bits: usize,
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        self.to_bits_with(ctx, value, num_bits, repr_bit)
+    }
+
+    // as `to_bits`, with `witness(value, i)` as the bit `i`, which only tests forging a
+    // decomposition set to anything else than `repr_bit`
+    pub(crate) fn to_bits_with(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        value: &AssignedValue<F>,
+        num_bits: usize,
+        witness: impl Fn(&F, usize) -> bool,
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        assert!(num_bits > 0 && num_bits <= F::NUM_BITS as usize);
+        let bits = (0..num_bits)
+            .map(|i| {
+                let bit = value.value().map(|v| F::from(witness(v, i) as u64));
+                self.assign_bit(ctx, bit)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let recomposed = self.from_bits(ctx, &bits)?;
+        ctx.constrain_equal(recomposed.cell(), value.cell())?;
+        Ok(bits)
+    }
+
+    /// Constrains `value` to be below `2^num_bits`, see [`Self::to_bits`]
+    pub fn range_check(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        value: &AssignedValue<F>,
+        num_bits: usize,
+    ) -> Result<(), Error> {
+        assert!(
+            num_bits < F::NUM_BITS as usize,
+            "every value fits in F::NUM_BITS bits"
+        );
+        self.to_bits(ctx, value, num_bits).map(|_| ())
+    }
+
+    /// Decomposes `value` into its `F::NUM_BITS` canonical little-endian bits, i.e. the
+    /// bits of an integer below the modulus, see [`Self::to_bits`]
+    pub fn to_bits_canonical(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        value: &AssignedValue<F>,
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        self.to_bits_canonical_with(ctx, value, repr_bit)
+    }
+
+    // as `to_bits_canonical`, with the bit witnesses of `to_bits_with`
+    pub(crate) fn to_bits_canonical_with(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        value: &AssignedValue<F>,
+        witness: impl Fn(&F, usize) -> bool,
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        let bits = self.to_bits_with(ctx, value, F::NUM_BITS as usize, witness)?;
+        self.assert_canonical::<F>(ctx, &bits)?;
+        Ok(bits)
+    }
+
+    // constrains the little-endian `bits` to be below the modulus of `B`: scanning them from
+    // the most significant one, a bit can't exceed the one of `p - 1` while the prefixes are
+    // equal
+    pub(crate) fn assert_canonical<B: PrimeField>(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        bits: &[AssignedValue<F>],
+    ) -> Result<(), Error> {
+        let max = -B::ONE;
+        // whether the prefixes are equal, `None` standing for the constant one
+        let mut eq: Option<AssignedValue<F>> = None;
+        for (i, bit) in bits.iter().enumerate().rev() {
+            eq = match (repr_bit(&max, i), eq.take()) {
+                (true, None) => Some(bit.clone()),
+                (true, Some(prefix)) => Some(self.and(ctx, &prefix, bit)?),
+                (false, None) => {
+                    self.assert_zero(ctx, bit)?;
+                    None
+                }
+                (false, Some(prefix)) => {
+                    let exceeds = self.and(ctx, &prefix, bit)?;
+                    self.assert_zero(ctx, &exceeds)?;
+                    Some(prefix)
+                }
+            };
+        }
+        Ok(())
+    }
+}
+
+// the bit `i` of the little-endian representation of `value`
+pub(crate) fn repr_bit<F: PrimeField>(value: &F, i: usize) -> bool {
+    (value.to_repr().as_ref()[i / 8] >> (i % 8)) & 1 == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Instance},
+    };
+    use halo2curves::pasta::Fp;
+
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct ArithmeticCircuitConfig {
+        config: MainGateConfig<3>,
+        instance: Column<Instance>,
+    }
+
+    // constrains the results of the arithmetic gadgets on `a` and `b` to the instance
+    struct ArithmeticCircuit {
+        a: Value<Fp>,
+        b: Value<Fp>,
+    }
+
+    impl Circuit<Fp> for ArithmeticCircuit {
+        type Config = ArithmeticCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                a: Value::unknown(),
+                b: Value::unknown(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); 5].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 10].map(|_| meta.fixed_column()).into_iter();
+            let config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            Self::Config { config, instance }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let outputs = layouter.assign_region(
+                || "arithmetic",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let main_gate = MainGate::<Fp, 3>::new(config.config.clone());
+                    let a = ctx.assign_advice(|| "a", config.config.input, self.a)?;
+                    let b = ctx.assign_advice(|| "b", config.config.out, self.b)?;
+                    ctx.next();
+
+                    let sum = main_gate.add(ctx, &a, &b)?;
+                    let commuted = main_gate.add(ctx, &b, &a)?;
+                    main_gate.assert_equal(ctx, &sum, &commuted)?;
+                    let diff = main_gate.sub(ctx, &sum, &commuted)?;
+                    main_gate.assert_zero(ctx, &diff)?;
+
+                    // two rows of the linear combination
+                    let terms = [(2u64, &a), (3, &b), (4, &a), (5, &b)]
+                        .map(|(coeff, value)| (Fp::from(coeff), value.clone()));
+                    Ok(vec![
+                        sum,
+                        main_gate.sub(ctx, &a, &b)?,
+                        main_gate.mul(ctx, &a, &b)?,
+                        main_gate.mul_add(ctx, &a, &b, &a)?,
+                        main_gate.add_constant(ctx, &a, Fp::from(7u64))?,
+                        main_gate.square(ctx, &a)?,
+                        main_gate.pow5(ctx, &a)?,
+                        main_gate.linear_combination(ctx, &terms, Fp::from(1u64))?,
+                    ])
+                },
+            )?;
+            for (row, output) in outputs.iter().enumerate() {
+                layouter.constrain_instance(output.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_arithmetic() {
+        const K: u32 = 6;
+        let (a, b) = (Fp::from(3u64), Fp::from(5u64));
+        let circuit = ArithmeticCircuit {
+            a: Value::known(a),
+            b: Value::known(b),
+        };
+        let expected = [
+            Fp::from(8u64),
+            -Fp::from(2u64),
+            Fp::from(15u64),
+            Fp::from(18u64),
+            Fp::from(10u64),
+            Fp::from(9u64),
+            Fp::from(243u64),
+            Fp::from(59u64),
+        ];
+        let prover = MockProver::run(K, &circuit, vec![expected.to_vec()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let mut wrong = expected;
+        wrong[6] += Fp::from(1u64);
+        let prover = MockProver::run(K, &circuit, vec![wrong.to_vec()]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // constrains `a` and `b` to be boolean and `x` and `y` to differ, and the results of the
+    // boolean, selection and comparison gadgets to the instance
+    struct BooleanCircuit {
+        a: Value<Fp>,
+        b: Value<Fp>,
+        x: Value<Fp>,
+        y: Value<Fp>,
+    }
+
+    impl Circuit<Fp> for BooleanCircuit {
+        type Config = ArithmeticCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                a: Value::unknown(),
+                b: Value::unknown(),
+                x: Value::unknown(),
+                y: Value::unknown(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            ArithmeticCircuit::configure(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let outputs = layouter.assign_region(
+                || "boolean",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let main_gate = MainGate::<Fp, 3>::new(config.config.clone());
+                    let [a, b, x, y] = [self.a, self.b, self.x, self.y];
+                    let a = ctx.assign_advice(|| "a", config.config.state[0], a)?;
+                    let b = ctx.assign_advice(|| "b", config.config.state[1], b)?;
+                    let x = ctx.assign_advice(|| "x", config.config.input, x)?;
+                    let y = ctx.assign_advice(|| "y", config.config.out, y)?;
+                    ctx.next();
+
+                    main_gate.assert_bool(ctx, &a)?;
+                    main_gate.assert_bool(ctx, &b)?;
+                    main_gate.assert_not_equal(ctx, &x, &y)?;
+                    let (first, second) = main_gate.conditional_swap(ctx, &b, &x, &y)?;
+                    Ok(vec![
+                        main_gate.and(ctx, &a, &b)?,
+                        main_gate.or(ctx, &a, &b)?,
+                        main_gate.xor(ctx, &a, &b)?,
+                        main_gate.not(ctx, &a)?,
+                        main_gate.select(ctx, &a, &x, &y)?,
+                        first,
+                        second,
+                        main_gate.is_zero(ctx, &x)?,
+                        main_gate.is_equal(ctx, &x, &y)?,
+                        main_gate.is_equal(ctx, &x, &x)?,
+                    ])
+                },
+            )?;
+            for (row, output) in outputs.iter().enumerate() {
+                layouter.constrain_instance(output.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_boolean() {
+        const K: u32 = 6;
+        let run = |a: u64, b: u64, x: u64, y: u64| {
+            let circuit = BooleanCircuit {
+                a: Value::known(Fp::from(a)),
+                b: Value::known(Fp::from(b)),
+                x: Value::known(Fp::from(x)),
+                y: Value::known(Fp::from(y)),
+            };
+            // the outputs as the formulas of the gadgets, whatever the booleanity
+            let [a, b, x, y] = [a, b, x, y].map(Fp::from);
+            let (first, second) = (x + b * (y - x), y - b * (y - x));
+            let expected = vec![
+                a * b,
+                a + b - a * b,
+                a + b - Fp::from(2u64) * a * b,
+                Fp::from(1u64) - a,
+                y + a * (x - y),
+                first,
+                second,
+                Fp::from((x == Fp::from(0u64)) as u64),
+                Fp::from((x == y) as u64),
+                Fp::from(1u64),
+            ];
+            let prover = MockProver::run(K, &circuit, vec![expected]).unwrap();
+            prover.verify()
+        };
+        for (a, b) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            assert_eq!(run(a, b, 3, 5), Ok(()));
+        }
+        assert_eq!(run(1, 1, 0, 5), Ok(()));
+
+        assert!(run(2, 0, 3, 5).is_err());
+        assert!(run(0, 2, 3, 5).is_err());
+        assert!(run(1, 0, 5, 5).is_err());
+    }
+
+    // constrains the inverse of `a`, `a / b` and the inverse or zero of `c` with its flag to
+    // the instance
+    struct InversionCircuit {
+        a: Value<Fp>,
+        b: Value<Fp>,
+        c: Value<Fp>,
+    }
+
+    impl Circuit<Fp> for InversionCircuit {
+        type Config = ArithmeticCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                a: Value::unknown(),
+                b: Value::unknown(),
+                c: Value::unknown(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            ArithmeticCircuit::configure(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let outputs = layouter.assign_region(
+                || "inversion",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let main_gate = MainGate::<Fp, 3>::new(config.config.clone());
+                    let a = ctx.assign_advice(|| "a", config.config.state[0], self.a)?;
+                    let b = ctx.assign_advice(|| "b", config.config.state[1], self.b)?;
+                    let c = ctx.assign_advice(|| "c", config.config.input, self.c)?;
+                    ctx.next();
+
+                    let (inverse, flag) = main_gate.invert_or_zero(ctx, &c)?;
+                    Ok(vec![
+                        main_gate.invert(ctx, &a)?,
+                        main_gate.div(ctx, &a, &b)?,
+                        inverse,
+                        flag,
+                    ])
+                },
+            )?;
+            for (row, output) in outputs.iter().enumerate() {
+                layouter.constrain_instance(output.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_inversion() {
+        const K: u32 = 5;
+        let run = |a: Fp, b: Fp, c: Fp, expected: Vec<Fp>| {
+            let circuit = InversionCircuit {
+                a: Value::known(a),
+                b: Value::known(b),
+                c: Value::known(c),
+            };
+            MockProver::run(K, &circuit, vec![expected])
+                .unwrap()
+                .verify()
+        };
+        let (a, b, c) = (Fp::from(3u64), Fp::from(5u64), Fp::from(7u64));
+        let (zero, one) = (Fp::from(0u64), Fp::from(1u64));
+        let inv = |v: Fp| ff::Field::invert(&v).unwrap();
+        assert_eq!(run(a, b, c, vec![inv(a), a * inv(b), inv(c), zero]), Ok(()));
+        assert_eq!(run(a, b, zero, vec![inv(a), a * inv(b), zero, one]), Ok(()));
+
+        // zero has no inverse
+        assert!(run(zero, b, c, vec![zero, zero, inv(c), zero]).is_err());
+        assert!(run(a, zero, c, vec![inv(a), zero, inv(c), zero]).is_err());
+        assert!(run(a, b, c, vec![inv(a), a * inv(b), zero, one]).is_err());
+    }
+
+    // range checks `value` to `num_bits` and decomposes it canonically, or checks that the
+    // given `bits` are canonical
+    struct BitsCircuit {
+        value: Value<Fp>,
+        num_bits: usize,
+        bits: Vec<Value<Fp>>,
+    }
+
+    impl Circuit<Fp> for BitsCircuit {
+        type Config = ArithmeticCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                value: Value::unknown(),
+                num_bits: self.num_bits,
+                bits: vec![Value::unknown(); self.bits.len()],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            ArithmeticCircuit::configure(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            layouter.assign_region(
+                || "bits",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let main_gate = MainGate::<Fp, 3>::new(config.config.clone());
+                    if self.bits.is_empty() {
+                        let value =
+                            ctx.assign_advice(|| "value", config.config.input, self.value)?;
+                        ctx.next();
+                        main_gate.range_check(ctx, &value, self.num_bits)?;
+                        main_gate.to_bits_canonical(ctx, &value)?;
+                    } else {
+                        let bits = self
+                            .bits
+                            .iter()
+                            .map(|bit| main_gate.assign_bit(ctx, *bit))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        main_gate.assert_canonical::<Fp>(ctx, &bits)?;
+                    }
+                    Ok(())
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn test_mock_bits() {
+        const K: u32 = 10;
+        let range_check = |value: Fp, num_bits| {
+            let circuit = BitsCircuit {
+                value: Value::known(value),
+                num_bits,
+                bits: Vec::new(),
+            };
+            MockProver::run(K, &circuit, vec![vec![]]).unwrap().verify()
+        };
+        assert_eq!(range_check(Fp::from(255u64), 8), Ok(()));
+        assert_eq!(range_check(Fp::from(0u64), 1), Ok(()));
+        assert!(range_check(Fp::from(256u64), 8).is_err());
+        assert!(range_check(-Fp::from(1u64), 254).is_err());
+
+        let assert_canonical = |bits: Vec<bool>| {
+            let circuit = BitsCircuit {
+                value: Value::unknown(),
+                num_bits: 0,
+                bits: bits
+                    .into_iter()
+                    .map(|bit| Value::known(Fp::from(bit as u64)))
+                    .collect(),
+            };
+            MockProver::run(K, &circuit, vec![vec![]]).unwrap().verify()
+        };
+        let mut bits = (0..Fp::NUM_BITS as usize)
+            .map(|i| repr_bit(&-Fp::from(1u64), i))
+            .collect::<Vec<_>>();
+        assert_eq!(assert_canonical(bits.clone()), Ok(()));
+        // p - 1 is even, hence the bits of p
+        bits[0] = true;
+        assert!(assert_canonical(bits.clone()).is_err());
+        // 2^255 - 1
+        assert!(assert_canonical(vec![true; Fp::NUM_BITS as usize]).is_err());
+    }
+
+    #[test]
+    fn test_sbox() {
+        use halo2curves::bn256::Fr;
+
+        // 3 divides p - 1 for both fields while 5, 7 and 17 do not
+        for sbox in [SBox::Pow5, SBox::Pow7, SBox::Pow17] {
+            assert!(sbox.is_valid::<Fp>() && sbox.is_valid::<Fr>());
+        }
+        assert!(!SBox::Pow3.is_valid::<Fp>() && !SBox::Pow3.is_valid::<Fr>());
+
+        let v = Fp::from(3u64);
+        assert_eq!(SBox::default().apply(v), Fp::from(243u64));
+        assert_eq!(SBox::Pow17.apply(v), Fp::from(3u64.pow(17)));
+    }
+
+    #[test]
+    fn test_sbox_rounds() {
+        // the minimal partial rounds of width 3 with 8 full rounds, before the security margin
+        for (sbox, r_p) in [(SBox::Pow5, 50), (SBox::Pow7, 40), (SBox::Pow17, 26)] {
+            assert!(sbox.rounds_are_secure::<Fp>(3, 8, r_p));
+            assert!(!sbox.rounds_are_secure::<Fp>(3, 8, r_p - 1));
+            assert!(!sbox.rounds_are_secure::<Fp>(3, 4, 3));
+        }
+        // the recommended rounds
+        assert!(SBox::Pow5.rounds_are_secure::<Fp>(3, 8, 57));
+        assert!(SBox::Pow5.rounds_are_secure::<Fp>(4, 8, 56));
+    }
+
+    // exposes `pow5` and the S-box of a gate configured with `x^7`
+    struct Pow5Circuit {
+        a: Value<Fp>,
+    }
+
+    impl Circuit<Fp> for Pow5Circuit {
+        type Config = ArithmeticCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                a: Value::unknown(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); 5].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 10].map(|_| meta.fixed_column()).into_iter();
+            let config =
+                MainGate::configure_with_sbox(meta, &mut adv_cols, &mut fix_cols, SBox::Pow7);
+            Self::Config { config, instance }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let outputs = layouter.assign_region(
+                || "pow5",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let main_gate = MainGate::<Fp, 3>::new(config.config.clone());
+                    let a = ctx.assign_advice(|| "a", config.config.input, self.a)?;
+                    ctx.next();
+                    Ok([main_gate.pow5(ctx, &a)?, main_gate.sbox(ctx, &a)?])
+                },
+            )?;
+            for (row, output) in outputs.iter().enumerate() {
+                layouter.constrain_instance(output.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_pow5() {
+        const K: u32 = 5;
+        let circuit = Pow5Circuit {
+            a: Value::known(Fp::from(3u64)),
+        };
+        let expected = vec![Fp::from(243u64), Fp::from(2187u64)];
+        let prover = MockProver::run(K, &circuit, vec![expected]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // `a^7` is not `a^5`
+        let wrong = vec![Fp::from(2187u64), Fp::from(2187u64)];
+        let prover = MockProver::run(K, &circuit, vec![wrong]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "x^3 is not a permutation of the field")]
+    fn test_invalid_sbox() {
+        let mut meta = ConstraintSystem::<Fp>::default();
+        let mut adv_cols = [(); 5].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 10].map(|_| meta.fixed_column()).into_iter();
+        MainGate::<Fp, 3>::configure_with_sbox(&mut meta, &mut adv_cols, &mut fix_cols, SBox::Pow3);
+    }
 }
@@ -0,0 +1,602 @@
+use ff::PrimeField;
+use halo2_proofs::{
+    circuit::{Chip, Value},
+    plonk::Error,
+};
+
+use crate::{
+    main_gate::{AssignedValue, MainGate, RegionCtx},
+    poseidon_circuit::{PoseidonChip, PoseidonConfig},
+    poseidon_hash::{Domain, PoseidonSpec},
+    sparse_merkle_tree::DEPTH,
+};
+
+// the main gate and the node hasher shared by the Merkle chips
+fn merkle_chips<F: PrimeField>(
+    config: impl Into<PoseidonConfig<3>>,
+    spec: PoseidonSpec<F, 3, 2>,
+) -> (MainGate<F, 3>, PoseidonChip<F, 3, 2>) {
+    let config = config.into();
+    let main_gate = match &config {
+        PoseidonConfig::MainGate(config) | PoseidonConfig::RoundGate(config, _) => {
+            MainGate::new(config.clone())
+        }
+    };
+    let poseidon = PoseidonChip::new(config, spec).with_domain(Domain::MerkleTree { arity: 2 });
+    (main_gate, poseidon)
+}
+
+// hashes `node` with `sibling`, `direction` being `1` when `node` is a right child, and
+// assumed to be boolean
+fn hash_with_sibling<F: PrimeField>(
+    main_gate: &MainGate<F, 3>,
+    poseidon: &PoseidonChip<F, 3, 2>,
+    ctx: &mut RegionCtx<'_, F>,
+    direction: &AssignedValue<F>,
+    node: &AssignedValue<F>,
+    sibling: &AssignedValue<F>,
+) -> Result<AssignedValue<F>, Error> {
+    let (left, right) = main_gate.conditional_swap(ctx, direction, node, sibling)?;
+    poseidon.compress(ctx, [left.into(), right.into()])
+}
+
+/// Verifies authentication paths of a [`crate::poseidon_hash::MerkleTree`]
+pub struct MerkleChip<F: PrimeField> {
+    main_gate: MainGate<F, 3>,
+    poseidon: PoseidonChip<F, 3, 2>,
+}
+
+impl<F: PrimeField> MerkleChip<F> {
+    pub fn new(config: impl Into<PoseidonConfig<3>>, spec: PoseidonSpec<F, 3, 2>) -> Self {
+        let (main_gate, poseidon) = merkle_chips(config, spec);
+        Self {
+            main_gate,
+            poseidon,
+        }
+    }
+
+    /// Computes the root of the tree containing `leaf` given its `siblings` from the leaf
+    /// level up and the matching `directions`, `1` when the node is a right child
+    ///
+    /// The directions are constrained to be boolean, they are the little-endian bits of the
+    /// leaf index.
+    pub fn root(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        leaf: &AssignedValue<F>,
+        siblings: &[AssignedValue<F>],
+        directions: &[AssignedValue<F>],
+    ) -> Result<AssignedValue<F>, Error> {
+        assert_eq!(siblings.len(), directions.len());
+        let mut node = leaf.clone();
+        for (sibling, direction) in siblings.iter().zip(directions.iter()) {
+            self.main_gate.assert_bool(ctx, direction)?;
+            node = hash_with_sibling(
+                &self.main_gate,
+                &self.poseidon,
+                ctx,
+                direction,
+                &node,
+                sibling,
+            )?;
+        }
+        Ok(node)
+    }
+
+    /// Constrains `leaf` to be in the tree of root `root`, see [`Self::root`]
+    pub fn verify(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        root: &AssignedValue<F>,
+        leaf: &AssignedValue<F>,
+        siblings: &[AssignedValue<F>],
+        directions: &[AssignedValue<F>],
+    ) -> Result<(), Error> {
+        let computed = self.root(ctx, leaf, siblings, directions)?;
+        ctx.constrain_equal(computed.cell(), root.cell())
+    }
+}
+
+/// Verifies membership and non-membership paths of a
+/// [`crate::sparse_merkle_tree::SparseMerkleTree`]
+pub struct SparseMerkleChip<F: PrimeField> {
+    main_gate: MainGate<F, 3>,
+    poseidon: PoseidonChip<F, 3, 2>,
+}
+
+impl<F: PrimeField> SparseMerkleChip<F> {
+    pub fn new(config: impl Into<PoseidonConfig<3>>, spec: PoseidonSpec<F, 3, 2>) -> Self {
+        let (main_gate, poseidon) = merkle_chips(config, spec);
+        Self {
+            main_gate,
+            poseidon,
+        }
+    }
+
+    /// Computes the root of the tree holding `value` at `key` given the [`DEPTH`] siblings of
+    /// its path from the leaf level up
+    ///
+    /// The directions are the canonical little-endian bits of `key`, so that a key has a
+    /// single leaf: `key` is range checked to `DEPTH` bits, which makes them canonical when
+    /// `F` has more bits, and they are constrained to be below the modulus otherwise.
+    pub fn root(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        key: &AssignedValue<F>,
+        value: &AssignedValue<F>,
+        siblings: &[AssignedValue<F>],
+    ) -> Result<AssignedValue<F>, Error> {
+        assert!(DEPTH <= F::NUM_BITS as usize);
+        assert_eq!(siblings.len(), DEPTH);
+        let bits = if DEPTH < F::NUM_BITS as usize {
+            self.main_gate.to_bits(ctx, key, DEPTH)?
+        } else {
+            self.main_gate.to_bits_canonical(ctx, key)?
+        };
+        let mut node = value.clone();
+        for (sibling, bit) in siblings.iter().zip(bits.iter()) {
+            node = hash_with_sibling(&self.main_gate, &self.poseidon, ctx, bit, &node, sibling)?;
+        }
+        Ok(node)
+    }
+
+    /// Constrains `key` to hold the non-zero `value` in the tree of root `root`
+    pub fn verify_membership(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        root: &AssignedValue<F>,
+        key: &AssignedValue<F>,
+        value: &AssignedValue<F>,
+        siblings: &[AssignedValue<F>],
+    ) -> Result<(), Error> {
+        self.main_gate.assert_not_zero(ctx, value)?;
+
+        let computed = self.root(ctx, key, value, siblings)?;
+        ctx.constrain_equal(computed.cell(), root.cell())
+    }
+
+    /// Constrains `key` to be absent from the tree of root `root`, i.e. its leaf is empty
+    pub fn verify_non_membership(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        root: &AssignedValue<F>,
+        key: &AssignedValue<F>,
+        siblings: &[AssignedValue<F>],
+    ) -> Result<(), Error> {
+        let empty = ctx.assign_advice(
+            || "empty leaf",
+            self.main_gate.config().input,
+            Value::known(F::ZERO),
+        )?;
+        ctx.next();
+        self.main_gate.assert_zero(ctx, &empty)?;
+
+        let computed = self.root(ctx, key, &empty, siblings)?;
+        ctx.constrain_equal(computed.cell(), root.cell())
+    }
+}
+
+/// Appends leaves to a [`crate::incremental_merkle_tree::IncrementalMerkleTree`]
+pub struct IncrementalMerkleChip<F: PrimeField> {
+    main_gate: MainGate<F, 3>,
+    poseidon: PoseidonChip<F, 3, 2>,
+}
+
+impl<F: PrimeField> IncrementalMerkleChip<F> {
+    pub fn new(config: impl Into<PoseidonConfig<3>>, spec: PoseidonSpec<F, 3, 2>) -> Self {
+        let (main_gate, poseidon) = merkle_chips(config, spec);
+        Self {
+            main_gate,
+            poseidon,
+        }
+    }
+
+    /// Appends `leaf` at index `old_len` to the tree of root `old_root`, frontier
+    /// `old_frontier` and `old_len` leaves, and returns the new root, the new frontier and
+    /// the new number of leaves
+    ///
+    /// The transition is proven by recomputing `old_root` from `old_frontier`: the path of
+    /// `old_len` takes its left siblings from the frontier at the set bits of `old_len`, and
+    /// its right siblings from the empty subtrees, computed here from a zero leaf, at the
+    /// other ones. `old_len` is range checked to `old_frontier.len()` bits, i.e. the tree is
+    /// not full.
+    ///
+    /// `old_len` has to be public or carried in the state along with `old_root`, e.g. as the
+    /// number of leaves returned by the previous append: the root alone does not bind it, as
+    /// the missing leaves are zero. Otherwise a prover could append past the last leaf, the
+    /// nodes of a partially filled left subtree passing for a frontier. Once `old_len` is
+    /// bound, so is `old_frontier`, which may then be private.
+    #[allow(clippy::type_complexity)]
+    pub fn append(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        old_root: &AssignedValue<F>,
+        old_frontier: &[AssignedValue<F>],
+        old_len: &AssignedValue<F>,
+        leaf: &AssignedValue<F>,
+    ) -> Result<(AssignedValue<F>, Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        let depth = old_frontier.len();
+        assert!(depth > 0 && depth < F::NUM_BITS as usize);
+        let bits = self.main_gate.to_bits(ctx, old_len, depth)?;
+
+        let mut empty = ctx.assign_advice(
+            || "empty leaf",
+            self.main_gate.config().input,
+            Value::known(F::ZERO),
+        )?;
+        ctx.next();
+        self.main_gate.assert_zero(ctx, &empty)?;
+
+        let mut old_node = empty.clone();
+        let mut new_node = leaf.clone();
+        let mut new_frontier = Vec::with_capacity(depth);
+        for (level, (bit, frontier)) in bits.iter().zip(old_frontier).enumerate() {
+            let sibling = self.main_gate.select(ctx, bit, frontier, &empty)?;
+            // the frontier is updated where the path is a left child
+            new_frontier.push(self.main_gate.select(ctx, bit, frontier, &new_node)?);
+            old_node = hash_with_sibling(
+                &self.main_gate,
+                &self.poseidon,
+                ctx,
+                bit,
+                &old_node,
+                &sibling,
+            )?;
+            new_node = hash_with_sibling(
+                &self.main_gate,
+                &self.poseidon,
+                ctx,
+                bit,
+                &new_node,
+                &sibling,
+            )?;
+            if level + 1 < depth {
+                empty = self
+                    .poseidon
+                    .compress(ctx, [(&empty).into(), (&empty).into()])?;
+            }
+        }
+        ctx.constrain_equal(old_node.cell(), old_root.cell())?;
+        let new_len = self.main_gate.add_constant(ctx, old_len, F::ONE)?;
+        Ok((new_node, new_frontier, new_len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner, Value},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use halo2curves::pasta::{EqAffine, Fp};
+
+    use super::*;
+    use crate::{
+        incremental_merkle_tree::IncrementalMerkleTree, main_gate::MainGateConfig,
+        poseidon_hash::MerkleTree, sparse_merkle_tree::SparseMerkleTree,
+    };
+
+    const R_F: usize = 4;
+    const R_P: usize = 3;
+
+    #[derive(Clone, Debug)]
+    struct MerkleCircuitConfig {
+        pconfig: MainGateConfig<3>,
+        instance: Column<Instance>,
+    }
+
+    // proves that the leaf at instance row 1 is in the tree of root at row 0
+    struct MerkleCircuit {
+        siblings: Vec<Value<Fp>>,
+        directions: Vec<Value<Fp>>,
+    }
+
+    impl Circuit<Fp> for MerkleCircuit {
+        type Config = MerkleCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                siblings: vec![Value::unknown(); self.siblings.len()],
+                directions: vec![Value::unknown(); self.directions.len()],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); 5].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 10].map(|_| meta.fixed_column()).into_iter();
+            let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            Self::Config { pconfig, instance }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, 3, 2>::new_unchecked(R_F, R_P);
+            layouter.assign_region(
+                || "merkle path",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut public = Vec::new();
+                    for row in 0..2 {
+                        public.push(ctx.region.assign_advice_from_instance(
+                            || "root and leaf",
+                            config.instance,
+                            row,
+                            config.pconfig.input,
+                            ctx.offset(),
+                        )?);
+                        ctx.next();
+                    }
+                    let mut assign = |values: &[Value<Fp>]| {
+                        values
+                            .iter()
+                            .map(|value| {
+                                let cell =
+                                    ctx.assign_advice(|| "witness", config.pconfig.input, *value);
+                                ctx.next();
+                                cell
+                            })
+                            .collect::<Result<Vec<_>, _>>()
+                    };
+                    let siblings = assign(&self.siblings)?;
+                    let directions = assign(&self.directions)?;
+
+                    let chip = MerkleChip::new(config.pconfig.clone(), spec.clone());
+                    chip.verify(ctx, &public[0], &public[1], &siblings, &directions)
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn test_mock_merkle_path() {
+        const K: u32 = 10;
+        let leaves = (0..8).map(|i| Fp::from(i as u64 + 10)).collect::<Vec<_>>();
+        let tree =
+            MerkleTree::<EqAffine, Fp>::new(PoseidonSpec::new_unchecked(R_F, R_P), leaves.clone());
+
+        for leaf_index in [0, 5, 7] {
+            let path = tree.path(leaf_index);
+            let circuit = MerkleCircuit {
+                siblings: path.siblings.iter().copied().map(Value::known).collect(),
+                directions: path
+                    .directions()
+                    .into_iter()
+                    .map(|d| Value::known(Fp::from(d as u64)))
+                    .collect(),
+            };
+            let prover =
+                MockProver::run(K, &circuit, vec![vec![tree.root(), leaves[leaf_index]]]).unwrap();
+            assert_eq!(prover.verify(), Ok(()));
+
+            // another leaf is not at this position
+            let other = leaves[leaf_index ^ 1];
+            let prover = MockProver::run(K, &circuit, vec![vec![tree.root(), other]]).unwrap();
+            assert!(prover.verify().is_err());
+        }
+
+        // non boolean directions are rejected
+        let path = tree.path(0);
+        let circuit = MerkleCircuit {
+            siblings: path.siblings.iter().copied().map(Value::known).collect(),
+            directions: vec![Value::known(Fp::from(2u64)); 3],
+        };
+        let prover = MockProver::run(K, &circuit, vec![vec![tree.root(), leaves[0]]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // proves that the key at instance row 1 holds the value at row 2 in the tree of root at
+    // row 0, or that it is absent when `membership` is unset
+    struct SparseMerkleCircuit {
+        membership: bool,
+        siblings: Vec<Value<Fp>>,
+    }
+
+    impl Circuit<Fp> for SparseMerkleCircuit {
+        type Config = MerkleCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                membership: self.membership,
+                siblings: vec![Value::unknown(); self.siblings.len()],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            MerkleCircuit::configure(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, 3, 2>::new_unchecked(R_F, R_P);
+            layouter.assign_region(
+                || "sparse merkle path",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut public = Vec::new();
+                    for row in 0..3 {
+                        public.push(ctx.region.assign_advice_from_instance(
+                            || "root, key and value",
+                            config.instance,
+                            row,
+                            config.pconfig.input,
+                            ctx.offset(),
+                        )?);
+                        ctx.next();
+                    }
+                    let mut siblings = Vec::new();
+                    for sibling in self.siblings.iter() {
+                        siblings.push(ctx.assign_advice(
+                            || "sibling",
+                            config.pconfig.input,
+                            *sibling,
+                        )?);
+                        ctx.next();
+                    }
+
+                    let chip = SparseMerkleChip::new(config.pconfig.clone(), spec.clone());
+                    if self.membership {
+                        chip.verify_membership(ctx, &public[0], &public[1], &public[2], &siblings)
+                    } else {
+                        chip.verify_non_membership(ctx, &public[0], &public[1], &siblings)
+                    }
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn test_mock_sparse_merkle_path() {
+        const K: u32 = 14;
+        let mut tree = SparseMerkleTree::<EqAffine, Fp>::new(PoseidonSpec::new_unchecked(R_F, R_P));
+        // the most significant bit of a key is bit 253
+        let key = Fp::from(2u64).pow_vartime([253]) + Fp::from(5u64);
+        let absent_key = Fp::from(6u64);
+        tree.insert(key, Fp::from(1u64));
+        tree.insert(Fp::from(7u64), Fp::from(2u64));
+
+        let run = |membership, key, value| {
+            let path = tree.prove(key);
+            let circuit = SparseMerkleCircuit {
+                membership,
+                siblings: path.siblings.iter().copied().map(Value::known).collect(),
+            };
+            let prover = MockProver::run(K, &circuit, vec![vec![tree.root(), key, value]]).unwrap();
+            prover.verify()
+        };
+        assert_eq!(run(true, key, Fp::from(1u64)), Ok(()));
+        assert_eq!(run(false, absent_key, Fp::from(0u64)), Ok(()));
+
+        assert!(run(true, key, Fp::from(2u64)).is_err());
+        assert!(run(false, key, Fp::from(0u64)).is_err());
+        // a zero value is not a membership
+        assert!(run(true, absent_key, Fp::from(0u64)).is_err());
+
+        // a key of 255 bits has no leaf, whatever the siblings
+        let circuit = SparseMerkleCircuit {
+            membership: false,
+            siblings: tree
+                .prove(absent_key)
+                .siblings
+                .into_iter()
+                .map(Value::known)
+                .collect(),
+        };
+        let public_inputs = vec![vec![tree.root(), -Fp::from(5u64), Fp::from(0u64)]];
+        let prover = MockProver::run(K, &circuit, public_inputs).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // appends the leaf at instance row 2 to the tree of root and number of leaves at rows 0
+    // and 1, the new root, number of leaves and frontier being at the next rows
+    struct IncrementalMerkleCircuit {
+        frontier: Vec<Value<Fp>>,
+    }
+
+    impl Circuit<Fp> for IncrementalMerkleCircuit {
+        type Config = MerkleCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                frontier: vec![Value::unknown(); self.frontier.len()],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            MerkleCircuit::configure(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, 3, 2>::new_unchecked(R_F, R_P);
+            layouter.assign_region(
+                || "incremental merkle append",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut public = Vec::new();
+                    for row in 0..3 {
+                        public.push(ctx.region.assign_advice_from_instance(
+                            || "old root, number of leaves and leaf",
+                            config.instance,
+                            row,
+                            config.pconfig.input,
+                            ctx.offset(),
+                        )?);
+                        ctx.next();
+                    }
+                    let mut frontier = Vec::new();
+                    for node in self.frontier.iter() {
+                        frontier.push(ctx.assign_advice(
+                            || "frontier",
+                            config.pconfig.input,
+                            *node,
+                        )?);
+                        ctx.next();
+                    }
+
+                    let chip = IncrementalMerkleChip::new(config.pconfig.clone(), spec.clone());
+                    let (root, frontier, len) =
+                        chip.append(ctx, &public[0], &frontier, &public[1], &public[2])?;
+                    for (row, cell) in [root, len].into_iter().chain(frontier).enumerate() {
+                        ctx.region
+                            .constrain_instance(cell.cell(), config.instance, 3 + row)?;
+                    }
+                    Ok(())
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn test_mock_incremental_merkle_append() {
+        const K: u32 = 10;
+        let mut tree =
+            IncrementalMerkleTree::<EqAffine, Fp>::new(PoseidonSpec::new_unchecked(R_F, R_P), 4);
+        for i in 0..5u64 {
+            tree.append(Fp::from(i + 10));
+        }
+        let (old_root, old_frontier) = (tree.root(), tree.frontier().to_vec());
+        let leaf = Fp::from(100u64);
+        let index = tree.append(leaf);
+
+        let run = |old_root, old_frontier: &[Fp], index| {
+            let circuit = IncrementalMerkleCircuit {
+                frontier: old_frontier.iter().copied().map(Value::known).collect(),
+            };
+            let mut public = vec![
+                old_root,
+                Fp::from(index),
+                leaf,
+                tree.root(),
+                Fp::from(tree.len()),
+            ];
+            public.extend(tree.frontier());
+            let prover = MockProver::run(K, &circuit, vec![public]).unwrap();
+            prover.verify()
+        };
+        assert_eq!(run(old_root, &old_frontier, index), Ok(()));
+
+        assert!(run(tree.roots()[4], &old_frontier, index).is_err());
+        // the old frontier is a valid path of `index + 1` too, the leaf can't land there
+        // while the number of leaves is the one of the tree
+        assert!(run(old_root, &old_frontier, index + 1).is_err());
+        let mut forged = old_frontier.clone();
+        forged[0] += Fp::from(1u64);
+        assert!(run(old_root, &forged, index).is_err());
+    }
+}
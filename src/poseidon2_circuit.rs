@@ -0,0 +1,345 @@
+use std::{convert::TryInto, mem};
+
+use ff::PrimeField;
+use halo2_proofs::{
+    circuit::{Chip, Value},
+    plonk::Error,
+};
+
+use crate::{
+    main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx, WrapValue},
+    poseidon2_hash::Poseidon2Spec,
+    poseidon_hash::Domain,
+};
+
+/// In-circuit [`Poseidon2Spec`] permutation
+///
+/// Every layer of the permutation takes `T` rows of [`MainGate`], one per state element. The
+/// constants of a round are added by the layer preceding it, so that the S-box is the `q_5`
+/// term and the matrices are its coefficients, or the ones of the `q_1` term for the elements
+/// left out of the S-box of a partial round.
+///
+/// The chip is a bare permutation, with [`Self::compress`] on top of it, and it keeps no
+/// sponge state: absorbing is left to the caller, block by block through
+/// [`Self::permutation`], as [`crate::poseidon2_hash::Poseidon2Hash`] does natively.
+pub struct Poseidon2Chip<F: PrimeField, const T: usize, const RATE: usize> {
+    main_gate: MainGate<F, T>,
+    spec: Poseidon2Spec<F, T>,
+}
+
+impl<F: PrimeField, const T: usize, const RATE: usize> Poseidon2Chip<F, T, RATE> {
+    pub fn new(config: MainGateConfig<T>, spec: Poseidon2Spec<F, T>) -> Self {
+        assert!(RATE > 0 && RATE < T);
+        assert_eq!(
+            config.sbox(),
+            spec.sbox(),
+            "the S-boxes of the gate and the spec differ"
+        );
+        Self {
+            main_gate: MainGate::new(config),
+            spec,
+        }
+    }
+
+    // the row of `out = sum_i(q_1[i] * s[i] + q_5[i] * sbox(s[i])) + rc`
+    fn layer_row(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        state: &[AssignedValue<F>; T],
+        q_1: [F; T],
+        q_5: [F; T],
+        rc: F,
+    ) -> Result<AssignedValue<F>, Error> {
+        let config = self.main_gate.config();
+        let sbox = config.sbox();
+        let mut out = Value::known(rc);
+        for (i, s) in state.iter().enumerate() {
+            let si =
+                ctx.assign_advice(|| "poseidon2: state", config.state[i], s.value().copied())?;
+            ctx.constrain_equal(s.cell(), si.cell())?;
+            ctx.assign_fixed(|| "poseidon2: q_1", config.q_1[i], q_1[i])?;
+            ctx.assign_fixed(|| "poseidon2: q_5", config.q_5[i], q_5[i])?;
+            out = out + s.value().map(|s| q_1[i] * s + q_5[i] * sbox.apply(*s));
+        }
+        ctx.assign_fixed(|| "poseidon2: rc", config.rc, rc)?;
+        ctx.assign_fixed(|| "poseidon2: q_o", config.q_o, -F::ONE)?;
+        let out = ctx.assign_advice(|| "poseidon2: out", config.out, out)?;
+        ctx.next();
+        Ok(out)
+    }
+
+    // a linear layer and the constants of the next round, `coefficients(i)` being the `q_1`
+    // and `q_5` of the element `i`
+    fn layer(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        state: &[AssignedValue<F>; T],
+        coefficients: impl Fn(usize) -> ([F; T], [F; T]),
+        rc: [F; T],
+    ) -> Result<[AssignedValue<F>; T], Error> {
+        let mut next_state = Vec::with_capacity(T);
+        for (i, rc) in rc.into_iter().enumerate() {
+            let (q_1, q_5) = coefficients(i);
+            next_state.push(self.layer_row(ctx, state, q_1, q_5, rc)?);
+        }
+        Ok(next_state.try_into().unwrap())
+    }
+
+    /// Permutes `state` as [`Poseidon2Spec::permute`], in `T * (r_f + r_p + 1)` rows
+    pub fn permute(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        state: &[AssignedValue<F>; T],
+    ) -> Result<[AssignedValue<F>; T], Error> {
+        let external = self.spec.external_matrix();
+        let internal = self.spec.internal_matrix();
+        let constants = self.spec.constants();
+
+        let mut state = self.layer(ctx, state, |i| (external[i], [F::ZERO; T]), constants[0])?;
+        for round in 0..constants.len() {
+            let rc = constants.get(round + 1).copied().unwrap_or([F::ZERO; T]);
+            state = if self.spec.is_full_round(round) {
+                self.layer(ctx, &state, |i| ([F::ZERO; T], external[i]), rc)?
+            } else {
+                let partial = |i: usize| {
+                    let mut q_1 = internal[i];
+                    let mut q_5 = [F::ZERO; T];
+                    q_5[0] = mem::replace(&mut q_1[0], F::ZERO);
+                    (q_1, q_5)
+                };
+                self.layer(ctx, &state, partial, rc)?
+            };
+        }
+        Ok(state)
+    }
+
+    // `inputs` are added to `state[1..=RATE]` of `state` or of the initial state, a partial
+    // block being padded with a single one. Every element takes a row: assigned inputs are
+    // copy-constrained into the `input` column while the padding and the initial state are
+    // folded into `rc`
+    fn pre_round(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: Vec<WrapValue<F>>,
+        state: Option<&[AssignedValue<F>; T]>,
+    ) -> Result<[AssignedValue<F>; T], Error> {
+        assert!(inputs.len() <= RATE);
+        let config = self.main_gate.config();
+        let initial_state = Domain::default().initial_state::<F, T>();
+
+        let mut next_state = Vec::with_capacity(T);
+        for (i, initial) in initial_state.into_iter().enumerate() {
+            let mut rc = if inputs.len() < RATE && i == inputs.len() + 1 {
+                F::ONE
+            } else {
+                F::ZERO
+            };
+            let s_val = match state {
+                Some(state) => {
+                    let s_val = state[i].value().copied();
+                    let si = ctx.assign_advice(|| "pre_round: state", config.state[i], s_val)?;
+                    ctx.constrain_equal(state[i].cell(), si.cell())?;
+                    ctx.assign_fixed(|| "pre_round: q_1", config.q_1[i], F::ONE)?;
+                    s_val
+                }
+                None => {
+                    rc += initial;
+                    Value::known(F::ZERO)
+                }
+            };
+
+            let input = match i {
+                0 => WrapValue::Zero,
+                i => inputs.get(i - 1).cloned().unwrap_or(WrapValue::Zero),
+            };
+            let input_val = match input {
+                WrapValue::Assigned(cell) => {
+                    let input = ctx.assign_advice(
+                        || "pre_round: input",
+                        config.input,
+                        cell.value().copied(),
+                    )?;
+                    ctx.constrain_equal(cell.cell(), input.cell())?;
+                    ctx.assign_fixed(|| "pre_round: q_i", config.q_i, F::ONE)?;
+                    cell.value().copied()
+                }
+                WrapValue::Unassigned(val) => {
+                    ctx.assign_advice(|| "pre_round: input", config.input, val)?;
+                    ctx.assign_fixed(|| "pre_round: q_i", config.q_i, F::ONE)?;
+                    val
+                }
+                WrapValue::Zero => Value::known(F::ZERO),
+            };
+
+            ctx.assign_fixed(|| "pre_round: rc", config.rc, rc)?;
+            ctx.assign_fixed(|| "pre_round: q_o", config.q_o, -F::ONE)?;
+            let out = ctx.assign_advice(
+                || "pre_round: out",
+                config.out,
+                s_val + input_val + Value::known(rc),
+            )?;
+            ctx.next();
+            next_state.push(out);
+        }
+        Ok(next_state.try_into().unwrap())
+    }
+
+    /// Permutes `init_state` (or the initial state) with `inputs` added to its rate part, see
+    /// [`crate::poseidon_circuit::PoseidonChip::permutation`]. Chaining the returned states
+    /// absorbs as [`crate::poseidon2_hash::Poseidon2Hash`], a partial block being padded with
+    /// a single one and an empty block following the last full one
+    pub fn permutation(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: Vec<WrapValue<F>>,
+        init_state: Option<&[AssignedValue<F>; T]>,
+    ) -> Result<[AssignedValue<F>; T], Error> {
+        let state = self.pre_round(ctx, inputs, init_state)?;
+        self.permute(ctx, &state)
+    }
+
+    /// Compresses `RATE` elements with a single permutation, see
+    /// [`crate::poseidon2_hash::Poseidon2Hash::compress`]
+    pub fn compress(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: [WrapValue<F>; RATE],
+    ) -> Result<AssignedValue<F>, Error> {
+        let state = self.permutation(ctx, inputs.to_vec(), None)?;
+        Ok(state[1].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::array;
+
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+    };
+    use halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::poseidon2_hash::Poseidon2Hash;
+
+    const R_F: usize = 8;
+    const R_P: usize = 56;
+
+    #[derive(Clone, Debug)]
+    struct Poseidon2CircuitConfig<const T: usize> {
+        pconfig: MainGateConfig<T>,
+        instance: Column<Instance>,
+    }
+
+    // exposes the permutation of `state`, the hash of `inputs` absorbed block by block and
+    // the compression of `pair` in the instance column
+    struct Poseidon2Circuit<const T: usize, const RATE: usize> {
+        state: [Value<Fr>; T],
+        inputs: Vec<Value<Fr>>,
+        pair: [Value<Fr>; RATE],
+    }
+
+    impl<const T: usize, const RATE: usize> Circuit<Fr> for Poseidon2Circuit<T, RATE> {
+        type Config = Poseidon2CircuitConfig<T>;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                state: [Value::unknown(); T],
+                inputs: vec![Value::unknown(); self.inputs.len()],
+                pair: [Value::unknown(); RATE],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let adv_cols = (0..T + 2).map(|_| meta.advice_column()).collect::<Vec<_>>();
+            let fix_cols = (0..2 * T + 4)
+                .map(|_| meta.fixed_column())
+                .collect::<Vec<_>>();
+            let pconfig =
+                MainGate::configure(meta, &mut adv_cols.into_iter(), &mut fix_cols.into_iter());
+            Self::Config { pconfig, instance }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let spec = Poseidon2Spec::<Fr, T>::new(R_F, R_P);
+            let outputs = layouter.assign_region(
+                || "poseidon2",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let chip =
+                        Poseidon2Chip::<Fr, T, RATE>::new(config.pconfig.clone(), spec.clone());
+                    let mut state = Vec::new();
+                    for s in self.state {
+                        state.push(ctx.assign_advice(|| "state", config.pconfig.input, s)?);
+                        ctx.next();
+                    }
+                    let mut outputs = chip.permute(ctx, &state.try_into().unwrap())?.to_vec();
+
+                    let inputs = self.inputs.iter().copied().map(WrapValue::from);
+                    let mut blocks = inputs
+                        .collect::<Vec<_>>()
+                        .chunks(RATE)
+                        .map(<[_]>::to_vec)
+                        .collect::<Vec<_>>();
+                    if self.inputs.len() % RATE == 0 {
+                        blocks.push(Vec::new());
+                    }
+                    let mut sponge = None;
+                    for block in blocks {
+                        sponge = Some(chip.permutation(ctx, block, sponge.as_ref())?);
+                    }
+                    outputs.push(sponge.unwrap()[1].clone());
+                    outputs.push(chip.compress(ctx, self.pair.map(WrapValue::from))?);
+                    Ok(outputs)
+                },
+            )?;
+            for (row, output) in outputs.iter().enumerate() {
+                layouter.constrain_instance(output.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn check_poseidon2<const T: usize, const RATE: usize>() {
+        const K: u32 = 11;
+        let spec = Poseidon2Spec::<Fr, T>::new(R_F, R_P);
+        let state = array::from_fn(|i| Fr::from(i as u64));
+        let inputs = [3u64, 4, 5].map(Fr::from);
+        let pair = array::from_fn(|i| Fr::from(6 + i as u64));
+
+        let mut permuted = state;
+        spec.permute(&mut permuted);
+        let mut hasher = Poseidon2Hash::<Fr, T, RATE>::new(spec);
+        hasher.update(&inputs);
+        let mut public_inputs = permuted.to_vec();
+        public_inputs.extend([hasher.output(), hasher.compress(pair)]);
+
+        let circuit = Poseidon2Circuit::<T, RATE> {
+            state: state.map(Value::known),
+            inputs: inputs.map(Value::known).to_vec(),
+            pair: pair.map(Value::known),
+        };
+        let prover = MockProver::run(K, &circuit, vec![public_inputs.clone()]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        public_inputs[T] += Fr::from(1u64);
+        let prover = MockProver::run(K, &circuit, vec![public_inputs]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_mock_poseidon2() {
+        // a partial block for a width of 3, a full one followed by a padding block for 4
+        check_poseidon2::<3, 2>();
+        check_poseidon2::<4, 3>();
+    }
+}
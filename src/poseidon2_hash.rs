@@ -0,0 +1,471 @@
+use std::{collections::VecDeque, mem};
+
+use halo2curves::group::ff::PrimeField;
+
+use crate::{main_gate::SBox, poseidon_hash::Domain};
+
+// Grain LFSR of appendix F of the [Poseidon paper](https://eprint.iacr.org/2019/458.pdf),
+// seeded as by the reference implementation of Poseidon2 for a prime field and `x^5`
+struct Grain {
+    bits: VecDeque<bool>,
+}
+
+impl Grain {
+    fn new(num_bits: usize, t: usize, r_f: usize, r_p: usize) -> Self {
+        let mut bits = VecDeque::with_capacity(80);
+        for (value, width) in [
+            (1, 2),
+            (0, 4),
+            (num_bits, 12),
+            (t, 12),
+            (r_f, 10),
+            (r_p, 10),
+        ] {
+            bits.extend((0..width).rev().map(|i| (value >> i) & 1 == 1));
+        }
+        bits.extend([true; 30]);
+        let mut grain = Self { bits };
+        for _ in 0..160 {
+            grain.step();
+        }
+        grain
+    }
+
+    fn step(&mut self) -> bool {
+        let bit = [62, 51, 38, 23, 13, 0]
+            .into_iter()
+            .fold(false, |acc, i| acc ^ self.bits[i]);
+        self.bits.pop_front();
+        self.bits.push_back(bit);
+        bit
+    }
+
+    // the output is filtered by pairs, the second bit is kept when the first one is set
+    fn next_bit(&mut self) -> bool {
+        loop {
+            let keep = self.step();
+            let bit = self.step();
+            if keep {
+                return bit;
+            }
+        }
+    }
+
+    // `F::NUM_BITS` big-endian bits, sampled again until they are below the modulus
+    fn next_field<F: PrimeField>(&mut self) -> F {
+        loop {
+            let bits = (0..F::NUM_BITS)
+                .map(|_| self.next_bit())
+                .collect::<Vec<_>>();
+            let mut repr = F::Repr::default();
+            for (i, bit) in bits.iter().rev().enumerate() {
+                if *bit {
+                    repr.as_mut()[i / 8] |= 1 << (i % 8);
+                }
+            }
+            if let Some(value) = Option::from(F::from_repr(repr)) {
+                return value;
+            }
+        }
+    }
+}
+
+fn apply_matrix<F: PrimeField, const T: usize>(matrix: &[[F; T]; T], state: &[F; T]) -> [F; T] {
+    matrix.map(|row| {
+        row.iter()
+            .zip(state.iter())
+            .fold(F::ZERO, |acc, (m, s)| acc + *m * s)
+    })
+}
+
+// external matrix of the reference implementation for a width of 4
+const M4: [[u64; 4]; 4] = [[5, 7, 1, 3], [4, 6, 1, 1], [1, 3, 5, 7], [1, 1, 4, 6]];
+
+// diagonals of the internal matrices of the reference implementation for the widths above 3,
+// minus one, by field modulus
+const INTERNAL_DIAGONALS: &[(&str, &[&str])] = &[
+    // BN254
+    (
+        "30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001",
+        &[
+            "10dc6e9c006ea38b04b1e03b4bd9490c0d03f98929ca1d7fb56821fd19d3b6e7",
+            "0c28145b6a44df3e0149b3d0a30b3bb599df9756d4dd9b84a86b38cfb45a740b",
+            "00544b8338791518b2c7645a50392798b21f75bb60e3596170067d00141cac15",
+            "222c01175718386f2e2e82eb122789e352e105a3b8fa852613bc534433ee428b",
+        ],
+    ),
+    // Pallas
+    (
+        "40000000000000000000000000000000224698fc094cf91b992d30ed00000001",
+        &[
+            "0767b051e5b6358fd12f217aae53bb9dac9a72a9f6a16fdde8f36e715bb27f51",
+            "2a59f16a37626bdd5536c5546f046b608c777734990103996730611728cfef21",
+            "2388405f3a1e87a1fd3183bb12a89c71b37555b4db6a4306e1f05322217ee15c",
+            "0e7c7e19ad92352c35e4d302828f64de68750dac64cbd944f0eba6c0ed003757",
+        ],
+    ),
+    (
+        "40000000000000000000000000000000224698fc094cf91b992d30ed00000001",
+        &[
+            "2527e8a83e49ae6bf3c8e459d5220e34d84aa49ce14f2dc401273cebdec65067",
+            "0e4a24b206b7494d2437d3e0fd1deeae8a943ccd836e0f959aaeccebb3068859",
+            "03c9638e9b8ad067e7033ed3aef5e185fcfa3959f82283bbf00c1bf0ea40fe45",
+            "09f7633edc22a16de93a8676260b507aa44aa8c57565bc5d21543897be56c100",
+            "06dfc4a91b7acb8ef203a8bc6b850290b1e272a594512ac0d1c9d3a56c8a7921",
+            "020e0af80c2a8e2aab6dcf5d8e94e71d24156e3123a16fdf8fd80471776e3551",
+            "24dfd0278f203a55322e94b290ae4269bbe76aa5531921f5f87a8d2d736dbb9c",
+            "08aa91c42dea2206ff4e601a1f49c009d18acc891ccb78529856db2a49664b2d",
+        ],
+    ),
+    // BLS12-381
+    (
+        "73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000001",
+        &[
+            "07564ad691bf01c8601d68757a561d224f00f313ada673ab83e6255fb4fd5b3d",
+            "6184e3be38549f7c0850cd069b32f6decbfde312dd4b8c18349b1b3776a6eaa4",
+            "419289088178ad742be6f78425c0156b6546a18fd338f0169937dea46cfb64d2",
+            "3244cdec173b71a4659e2529b499362dac10cb2fd17562860c8bb9d0fd45b787",
+        ],
+    ),
+    (
+        "73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000001",
+        &[
+            "1118b610c2544efa26b70d9d60ca6ca362afcfff12436cf3b0f8a3ec5895d9ea",
+            "5ba288c5197e71745a8fde16aca575e379dcc19f21042d8b9375e478f809325b",
+            "079a987d87d7c80d5f4a3b4018517c50f5067ecb516f6bd14d79eabaa8349e62",
+            "4c6497b0b99e1f1af4ec0322dc38869b2dfb79db3ab5fa68936cc8b6025aad1f",
+            "483b5c5071e90c98bd353556453f04113442f29a1c4c236b4ca31890136bee4d",
+            "3ef76c8bae0aa755dde594d8ec22b157f913323e5b29bbd0652e4b74973ac8f9",
+            "091767b280c59a58a39f293bfc22ae944cb921c2efa240262b5b66312724f20b",
+            "45ef82a5684137e5fc9613e0581cb65b5ad3d43470eacf0f060e1711c4c57623",
+        ],
+    ),
+];
+
+fn from_hex<F: PrimeField>(hex: &str) -> F {
+    hex.chars().fold(F::ZERO, |acc, c| {
+        acc * F::from(16) + F::from(c.to_digit(16).expect("not a hex digit") as u64)
+    })
+}
+
+fn reference_diagonal<F: PrimeField, const T: usize>() -> Option<[F; T]> {
+    let modulus = F::MODULUS.trim_start_matches("0x").to_lowercase();
+    INTERNAL_DIAGONALS
+        .iter()
+        .find(|(m, diagonal)| *m == modulus && diagonal.len() == T)
+        .map(|(_, diagonal)| {
+            let mut values = [F::ZERO; T];
+            for (value, hex) in values.iter_mut().zip(diagonal.iter()) {
+                *value = from_hex(hex);
+            }
+            values
+        })
+}
+
+/// Parameters of the [Poseidon2](https://eprint.iacr.org/2023/323.pdf) permutation of width
+/// `T`, with the `x^5` S-box unless set by [`Self::with_sbox`]
+///
+/// Every round adds its constants then applies the S-box, to the whole state in the full
+/// rounds and to its first element in the partial ones, and then the external or the internal
+/// matrix. The external matrix is also applied once before the first round.
+///
+/// As in the reference implementation, the external matrix is `1 + I` for the widths 2 and 3,
+/// and for the multiples of 4 up to 24 it is `M4` on every block of 4 elements, the blocks
+/// being then mixed by `circ(2, 1, .., 1)`. The internal matrix is `1 + diag(d)`.
+#[derive(Clone, Debug)]
+pub struct Poseidon2Spec<F: PrimeField, const T: usize> {
+    r_f: usize,
+    // constants of every round, only the first one is used in the partial rounds and the
+    // others are zero
+    constants: Vec<[F; T]>,
+    external: [[F; T]; T],
+    internal: [[F; T]; T],
+    sbox: SBox,
+}
+
+impl<F: PrimeField, const T: usize> Poseidon2Spec<F, T> {
+    /// `r_f` full rounds, half of them before the `r_p` partial rounds. The constants are the
+    /// ones of the reference implementation, drawn from the Grain LFSR for the full rounds
+    /// and the first element of the partial ones. The internal diagonal is `(1, .., 1, 2)` for
+    /// the widths 2 and 3, above them it is the one of the reference implementation, which is
+    /// only given for BN254 with a width of 4 and for Pallas and BLS12-381 with widths of 4
+    /// and 8, see [`Self::new_with_diagonal`] for the other cases. It panics unless `x^5` is
+    /// a permutation of `F` and the rounds are enough for it, see [`SBox::rounds_are_secure`]
+    pub fn new(r_f: usize, r_p: usize) -> Self {
+        let diagonal = if T <= 3 {
+            let mut diagonal = [F::ONE; T];
+            diagonal[T - 1] = F::from(2);
+            diagonal
+        } else {
+            reference_diagonal().expect("no reference internal matrix for this field and width")
+        };
+        Self::new_with_diagonal(r_f, r_p, diagonal)
+    }
+
+    /// As [`Self::new`] with the internal matrix `1 + diag(diagonal)`, which must make the
+    /// permutation secure as explained in the section 5.3 of the paper
+    pub fn new_with_diagonal(r_f: usize, r_p: usize, diagonal: [F; T]) -> Self {
+        assert!(
+            T == 2 || T == 3 || (T % 4 == 0 && T <= 24),
+            "the width is 2, 3 or a multiple of 4 up to 24"
+        );
+        assert!(r_f > 0 && r_f % 2 == 0);
+        SBox::default().validate::<F>();
+        SBox::default().validate_rounds::<F>(T, r_f, r_p);
+        let mut grain = Grain::new(F::NUM_BITS as usize, T, r_f, r_p);
+        let constants = (0..r_f + r_p)
+            .map(|round| {
+                if round < r_f / 2 || round >= r_f / 2 + r_p {
+                    [(); T].map(|_| grain.next_field())
+                } else {
+                    let mut constants = [F::ZERO; T];
+                    constants[0] = grain.next_field();
+                    constants
+                }
+            })
+            .collect();
+
+        let mut external = [[F::ONE; T]; T];
+        let mut internal = [[F::ONE; T]; T];
+        for (i, (external_row, internal_row)) in
+            external.iter_mut().zip(internal.iter_mut()).enumerate()
+        {
+            if T < 4 {
+                external_row[i] += F::ONE;
+            } else {
+                for (j, m) in external_row.iter_mut().enumerate() {
+                    let block = if T > 4 && i / 4 == j / 4 { 2 } else { 1 };
+                    *m = F::from(block * M4[i % 4][j % 4]);
+                }
+            }
+            internal_row[i] += diagonal[i];
+        }
+        Self {
+            r_f,
+            constants,
+            external,
+            internal,
+            sbox: SBox::default(),
+        }
+    }
+
+    /// Sets the S-box, it panics unless it is a permutation of `F` and the rounds are enough
+    /// for it, see [`SBox::rounds_are_secure`]. The constants do not depend on it, as in the
+    /// reference implementation
+    pub fn with_sbox(mut self, sbox: SBox) -> Self {
+        sbox.validate::<F>();
+        sbox.validate_rounds::<F>(T, self.r_f, self.r_p());
+        self.sbox = sbox;
+        self
+    }
+
+    pub fn sbox(&self) -> SBox {
+        self.sbox
+    }
+
+    pub fn r_f(&self) -> usize {
+        self.r_f
+    }
+
+    pub fn r_p(&self) -> usize {
+        self.constants.len() - self.r_f
+    }
+
+    /// Constants of every round, see [`Poseidon2Spec`]
+    pub fn constants(&self) -> &[[F; T]] {
+        &self.constants
+    }
+
+    pub fn external_matrix(&self) -> &[[F; T]; T] {
+        &self.external
+    }
+
+    pub fn internal_matrix(&self) -> &[[F; T]; T] {
+        &self.internal
+    }
+
+    pub fn is_full_round(&self, round: usize) -> bool {
+        round < self.r_f / 2 || round >= self.r_f / 2 + self.r_p()
+    }
+
+    pub fn permute(&self, state: &mut [F; T]) {
+        *state = apply_matrix(&self.external, state);
+        for (round, constants) in self.constants.iter().enumerate() {
+            if self.is_full_round(round) {
+                for (s, c) in state.iter_mut().zip(constants.iter()) {
+                    *s = self.sbox.apply(*s + c);
+                }
+                *state = apply_matrix(&self.external, state);
+            } else {
+                state[0] = self.sbox.apply(state[0] + constants[0]);
+                *state = apply_matrix(&self.internal, state);
+            }
+        }
+    }
+}
+
+/// Sponge over the [`Poseidon2Spec`] permutation, absorbing and padding as
+/// [`crate::poseidon_hash::PoseidonHash`] does in the default [`Domain`]
+#[derive(Clone, Debug)]
+pub struct Poseidon2Hash<F: PrimeField, const T: usize, const RATE: usize> {
+    spec: Poseidon2Spec<F, T>,
+    state: [F; T],
+    buf: Vec<F>,
+}
+
+impl<F: PrimeField, const T: usize, const RATE: usize> Poseidon2Hash<F, T, RATE> {
+    pub fn new(spec: Poseidon2Spec<F, T>) -> Self {
+        assert!(RATE > 0 && RATE < T);
+        Self {
+            spec,
+            state: Domain::default().initial_state(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Buffers elements to be absorbed by the next [`Self::output`]
+    pub fn update(&mut self, elements: &[F]) {
+        self.buf.extend_from_slice(elements);
+    }
+
+    /// Absorbs the buffered elements with padding and returns the first element of the rate
+    pub fn output(&mut self) -> F {
+        let buf = mem::take(&mut self.buf);
+        for chunk in buf.chunks(RATE) {
+            self.absorb_block(chunk);
+        }
+        if buf.len() % RATE == 0 {
+            self.absorb_block(&[]);
+        }
+        self.state[1]
+    }
+
+    /// Compresses `RATE` elements with a single permutation of
+    /// `[capacity, inputs[0], .., inputs[RATE - 1]]`, the sponge itself is left untouched
+    pub fn compress(&self, inputs: [F; RATE]) -> F {
+        let mut state: [F; T] = Domain::default().initial_state();
+        state[1..=RATE].copy_from_slice(&inputs);
+        self.spec.permute(&mut state);
+        state[1]
+    }
+
+    // `inputs` are added to `state[1..=RATE]`, a partial block being padded with a single one
+    fn absorb_block(&mut self, inputs: &[F]) {
+        for (s, input) in self.state.iter_mut().skip(1).zip(inputs) {
+            *s += input;
+        }
+        if inputs.len() < RATE {
+            self.state[1 + inputs.len()] += F::ONE;
+        }
+        self.spec.permute(&mut self.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bls12_381::Scalar;
+    use halo2curves::{bn256::Fr, pasta::Fp};
+
+    use super::*;
+
+    #[test]
+    fn test_poseidon2_permutation() {
+        // the vector of the reference implementation for BN254 with a width of 3
+        let spec = Poseidon2Spec::<Fr, 3>::new(8, 56);
+        let mut state = [0u64, 1, 2].map(Fr::from);
+        spec.permute(&mut state);
+        let expected = [
+            // 0x0bb61d24daca55eebcb1929a82650f328134334da98ea4f847f760054f4a3033
+            "5297208644449048816064511434384511824916970985131888684874823260532015509555",
+            // 0x303b6f7c86d043bfcbcc80214f26a30277a15d3f74ca654992defe7ff8d03570
+            "21816030159894113985964609355246484851575571273661473159848781012394295965040",
+            // 0x1ed25194542b12eef8617361c3ba7c52e660b145994427cc86296242cf766ec8
+            "13940986381491601233448981668101586453321811870310341844570924906201623195336",
+        ]
+        .map(|value| Fr::from_str_vartime(value).unwrap());
+        assert_eq!(state, expected);
+
+        // and with a width of 4
+        let spec = Poseidon2Spec::<Fr, 4>::new(8, 56);
+        let mut state = [0u64, 1, 2, 3].map(Fr::from);
+        spec.permute(&mut state);
+        let expected = [
+            // 0x01bd538c2ee014ed5141b29e9ae240bf8db3fe5b9a38629a9647cf8d76c01737
+            "786823568102245344938517132468097745676732687098822989626730198331658606391",
+            // 0x239b62e7db98aa3a2a8f6a0d2fa1709e7a35959aa6c7034814d9daa90cbac662
+            "16105493617470833344375945651585194737369509580406730765188791202038211593826",
+            // 0x04cbb44c61d928ed06808456bf758cbf0c18d1e15a7b6dbc8245fa7515d5e3cb
+            "2169165722086073256768101917994796590773204847633762971322389403847680713675",
+            // 0x2e11c5cff2a22c64d01304b778d78f6998eff1ab73163a35603f54794c30847a
+            "20837792685223053096472825292260687493226094382304778455120670180090619921530",
+        ]
+        .map(|value| Fr::from_str_vartime(value).unwrap());
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn test_poseidon2_permutation_bls12() {
+        // the vectors of the reference implementation for BLS12-381 with widths of 2 and 4
+        let spec = Poseidon2Spec::<Scalar, 2>::new(8, 56);
+        let mut state = [0u64, 1].map(Scalar::from);
+        spec.permute(&mut state);
+        let expected = [
+            // 0x73c46dd530e248a87b61d19e67fa1b4ed30fc3d09f16531fe189fb945a15ce4e
+            "52363037649740716030766703085959398089247109082552119369664912680823987490382",
+            // 0x1f0e305ee21c9366d5793b80251405032a3fee32b9dd0b5f4578262891b043b4
+            "14046768006860121393795564977526877746513733133602551947789337117320794882996",
+        ]
+        .map(|value| Scalar::from_str_vartime(value).unwrap());
+        assert_eq!(state, expected);
+
+        let spec = Poseidon2Spec::<Scalar, 4>::new(8, 56);
+        let mut state = [0u64, 1, 2, 3].map(Scalar::from);
+        spec.permute(&mut state);
+        let expected = [
+            // 0x28ff6c4edf9768c08ae26290487e93449cc8bc155fc2fad92a344adceb3ada6d
+            "18543807459877364126908352029685462281488659954858558933392966590401135434349",
+            // 0x0e56f2b6fad25075aa93560185b70e2b180ed7e269159c507c288b6747a0db2d
+            "6486003883477478427469307271780113565813206904808083202373087381132280912685",
+            // 0x6d8196f28da6006bb89b3df94600acdc03d0ba7c2b0f3f4409a54c1db6bf30d0
+            "49531065568108882186803339532638377985898634706171140442815716917306497904848",
+            // 0x07cfb49540ee456cce38b8a7d1a930a57ffc6660737f6589ef184c5e15334e36
+            "3533173620704471731420930490618911963424635467018145440601039502713526898230",
+        ]
+        .map(|value| Scalar::from_str_vartime(value).unwrap());
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "no reference internal matrix for this field and width")]
+    fn test_poseidon2_no_reference_diagonal() {
+        Poseidon2Spec::<Fr, 8>::new(8, 57);
+    }
+
+    #[test]
+    fn test_poseidon2_hash() {
+        let spec = Poseidon2Spec::<Fp, 3>::new(8, 56);
+        assert_eq!(spec.r_p(), 56);
+        assert_eq!(spec.constants().len(), 64);
+
+        let mut hasher = Poseidon2Hash::<Fp, 3, 2>::new(spec.clone());
+        hasher.update(&[Fp::from(1u64), Fp::from(2u64)]);
+        let output = hasher.output();
+
+        // a full block is followed by a padding block
+        let mut state: [Fp; 3] = Domain::default().initial_state();
+        state[1] += Fp::from(1u64);
+        state[2] += Fp::from(2u64);
+        spec.permute(&mut state);
+        assert_eq!(hasher.compress([Fp::from(1u64), Fp::from(2u64)]), state[1]);
+        state[1] += Fp::from(1u64);
+        spec.permute(&mut state);
+        assert_eq!(output, state[1]);
+
+        // the sponge keeps absorbing after a squeeze
+        hasher.update(&[Fp::from(3u64)]);
+        state[1] += Fp::from(3u64);
+        state[2] += Fp::from(1u64);
+        spec.permute(&mut state);
+        assert_eq!(hasher.output(), state[1]);
+    }
+}
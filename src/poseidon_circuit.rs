@@ -1,90 +1,187 @@
-use std::convert::TryInto;
+use std::{convert::TryInto, mem};
 
-use ff::PrimeField;
+use ff::{FromUniformBytes, PrimeField};
 use halo2_proofs::{
+    arithmetic::CurveAffine,
     circuit::{AssignedCell, Chip, Value},
     plonk::Error,
 };
 use poseidon::Spec;
 
-use crate::main_gate::{AssignedValue, MainGate, MainGateConfig, RegionCtx};
+use crate::{
+    main_gate::{repr_bit, AssignedValue, MainGate, MainGateConfig, RegionCtx, WrapValue},
+    poseidon_hash::{Domain, PoseidonSpec},
+    ro_types::{AssignedBase, AssignedBasePoint, AssignedPoint, ROCircuitTrait},
+    round_gate::{Round, RoundGate, RoundGateConfig},
+};
+
+/// Selects how [`PoseidonChip`] lays out the rounds of a permutation
+#[derive(Clone, Debug)]
+pub enum PoseidonConfig<const T: usize> {
+    /// Every round takes `T` rows of [`MainGate`], one per state element
+    MainGate(MainGateConfig<T>),
+    /// Rounds following the pre-round take a single row of [`RoundGate`] each
+    RoundGate(MainGateConfig<T>, RoundGateConfig<T>),
+}
+
+impl<const T: usize> From<MainGateConfig<T>> for PoseidonConfig<T> {
+    fn from(config: MainGateConfig<T>) -> Self {
+        PoseidonConfig::MainGate(config)
+    }
+}
 
+/// In-circuit duplex sponge over [`MainGate`]
+///
+/// The sponge state is kept between squeezes, so a chip that is mutated inside
+/// `Layouter::assign_region` should be created within the closure: floor planners
+/// may call it more than once.
 pub struct PoseidonChip<F: PrimeField, const T: usize, const RATE: usize> {
     main_gate: MainGate<F, T>,
+    round_gate: Option<RoundGate<F, T>>,
     spec: Spec<F, T, RATE>,
-    buf: Vec<F>,
+    buf: Vec<WrapValue<F>>,
+    // `None` until the first permutation, the initial state is a constant
+    state: Option<[AssignedValue<F>; T]>,
+    // elements absorbed and squeezed per permutation, `RATE` unless set by `with_rate`
+    rate: usize,
+    domain: Domain,
 }
 
 impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE> {
-    pub fn new(config: MainGateConfig<T>, spec: Spec<F, T, RATE>) -> Self {
-        let main_gate: MainGate<F, T> = MainGate::new(config);
+    /// The S-box of `spec` has to be the one of the gate, it panics otherwise
+    pub fn new(config: impl Into<PoseidonConfig<T>>, spec: PoseidonSpec<F, T, RATE>) -> Self {
+        let (main_gate, round_gate) = match config.into() {
+            PoseidonConfig::MainGate(config) => (MainGate::new(config), None),
+            PoseidonConfig::RoundGate(config, round_config) => {
+                (MainGate::new(config), Some(RoundGate::new(round_config)))
+            }
+        };
+        assert_eq!(
+            main_gate.config().sbox(),
+            spec.sbox(),
+            "the S-boxes of the gate and the spec differ"
+        );
         Self {
             main_gate,
-            spec,
+            round_gate,
+            spec: spec.spec().clone(),
             buf: Vec::new(),
+            state: None,
+            rate: RATE,
+            domain: Domain::default(),
         }
     }
 
+    /// Sets the rate of the sponge, see [`crate::poseidon_hash::PoseidonHash::with_rate`]
+    pub fn with_rate(mut self, rate: usize) -> Self {
+        assert!(rate > 0 && rate < T);
+        self.rate = rate;
+        self
+    }
+
+    /// Sets the domain of the sponge, see [`crate::poseidon_hash::PoseidonHash::with_domain`]
+    pub fn with_domain(mut self, domain: Domain) -> Self {
+        assert!(
+            self.buf.is_empty() && self.state.is_none(),
+            "nothing can be absorbed or squeezed before the domain is set"
+        );
+        self.domain = domain;
+        self
+    }
+
+    fn initial_state(&self) -> [F; T] {
+        self.domain.initial_state()
+    }
+
+    /// The `out` of a row of the main gate, with its S-box
     pub fn next_state_val(
+        &self,
         state: [Value<F>; T],
         q_1: [F; T],
         q_5: [F; T],
         q_o: F,
         rc: F,
     ) -> Value<F> {
-        let pow_5 = |v: Value<F>| {
-            let v2 = v * v;
-            v2 * v2 * v
-        };
+        let sbox = self.main_gate.config().sbox;
         let mut out = Value::known(rc);
         for ((s, q1), q5) in state.iter().zip(q_1).zip(q_5) {
-            out = out + pow_5(*s) * Value::known(q5) + *s * Value::known(q1);
+            out = out + sbox.apply_value(*s) * Value::known(q5) + *s * Value::known(q1);
         }
         out * Value::known((-q_o).invert().unwrap())
     }
 
+    // the absorbed element of `state_idx` goes through the `input` column: assigned cells
+    // are copy-constrained into it, while the padding element is a constant folded into `rc`,
+    // and so is the initial state when `state` is `None`
     pub fn pre_round(
         &self,
         ctx: &mut RegionCtx<'_, F>,
-        inputs: Vec<F>,
+        inputs: Vec<WrapValue<F>>,
         state_idx: usize,
-        state: &[AssignedValue<F>; T],
+        state: Option<&[AssignedValue<F>; T]>,
     ) -> Result<AssignedValue<F>, Error> {
-        assert!(inputs.len() <= RATE);
-        let s_val = state[state_idx].value().copied();
-
-        let inputs = std::iter::once(F::ZERO)
-            .chain(inputs)
-            .chain(std::iter::once(F::ONE))
-            .chain(std::iter::repeat(F::ZERO))
-            .take(T)
-            .collect::<Vec<_>>();
-        let input_val = Value::known(inputs[state_idx]);
+        assert!(inputs.len() <= self.rate);
+
+        let input = match state_idx {
+            0 => WrapValue::Zero,
+            idx => inputs.get(idx - 1).cloned().unwrap_or(WrapValue::Zero),
+        };
+        let padding = if inputs.len() < self.rate && state_idx == inputs.len() + 1 {
+            F::ONE
+        } else {
+            F::ZERO
+        };
 
         let constants = self.spec.constants().start();
         let pre_constants = constants[0];
-        let rc_val = pre_constants[state_idx];
+        let mut rc_val = pre_constants[state_idx] + padding;
 
-        let out_val = s_val + input_val + Value::known(rc_val);
+        let s_val = match state {
+            Some(state) => {
+                let s_val = state[state_idx].value().copied();
+                let si = ctx.assign_advice(
+                    || "first round: state",
+                    self.main_gate.config().state[state_idx],
+                    s_val,
+                )?;
+                ctx.constrain_equal(state[state_idx].cell(), si.cell())?;
+                ctx.assign_fixed(
+                    || "pre_round: q_1",
+                    self.main_gate.config().q_1[state_idx],
+                    F::ONE,
+                )?;
+                s_val
+            }
+            None => {
+                let s_val = self.initial_state()[state_idx];
+                rc_val += s_val;
+                Value::known(s_val)
+            }
+        };
 
-        let si = ctx.assign_advice(
-            || "first round: state",
-            self.main_gate.config().state[state_idx],
-            s_val,
-        )?;
-        ctx.constrain_equal(state[state_idx].cell(), si.cell())?;
+        let input_val = match input {
+            WrapValue::Assigned(cell) => {
+                let input = ctx.assign_advice(
+                    || "pre_round: input",
+                    self.main_gate.config().input,
+                    cell.value().copied(),
+                )?;
+                ctx.constrain_equal(cell.cell(), input.cell())?;
+                Some(cell.value().copied())
+            }
+            WrapValue::Unassigned(val) => {
+                ctx.assign_advice(|| "pre_round: input", self.main_gate.config().input, val)?;
+                Some(val)
+            }
+            WrapValue::Zero => None,
+        };
+        if input_val.is_some() {
+            ctx.assign_fixed(|| "pre_round: q_i", self.main_gate.config().q_i, F::ONE)?;
+        }
+        let out_val = s_val
+            + input_val.unwrap_or(Value::known(F::ZERO))
+            + Value::known(pre_constants[state_idx] + padding);
 
-        ctx.assign_advice(
-            || "pre_round: input",
-            self.main_gate.config().input,
-            input_val,
-        )?;
-        ctx.assign_fixed(
-            || "pre_round: q_1",
-            self.main_gate.config().q_1[state_idx],
-            F::ONE,
-        )?;
-        ctx.assign_fixed(|| "pre_round: q_i", self.main_gate.config().q_i, F::ONE)?;
         ctx.assign_fixed(|| "pre_round: q_o", self.main_gate.config().q_o, -F::ONE)?;
         ctx.assign_fixed(|| "pre_round: rc", self.main_gate.config().rc, rc_val)?;
         let out = ctx.assign_advice(|| "pre_round: out", self.main_gate.config().out, out_val)?;
@@ -93,20 +190,13 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         Ok(out)
     }
 
-    // round_idx \in [0; r_f - 1] indicates the round index of either first half full or second half full
-    pub fn full_round(
+    // mds matrix and constants of a full round, the constants are moved through the matrix
+    // so that the round computes `mds * sbox(s) + rc`
+    fn full_round_constants(
         &self,
-        ctx: &mut RegionCtx<'_, F>,
         is_first_half_full: bool,
         round_idx: usize,
-        state_idx: usize,
-        state: &[AssignedCell<F, F>; T],
-    ) -> Result<AssignedCell<F, F>, Error> {
-        let mut state_vals = [Value::known(F::ZERO); T];
-        let q_1_vals = [F::ZERO; T];
-        let mut q_5_vals = [F::ZERO; T];
-        let q_o_val = -F::ONE;
-
+    ) -> ([[F; T]; T], [F; T]) {
         let r_f = self.spec.r_f() / 2;
         let constants = if is_first_half_full {
             self.spec.constants().start()
@@ -126,11 +216,31 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         } else {
             self.spec.mds_matrices().mds().rows()
         };
-        let mds_row = mds[state_idx];
+        let rc = mds.map(|row| {
+            row.iter()
+                .zip(rcs.iter())
+                .fold(F::ZERO, |acc, (mij, cj)| acc + *mij * cj)
+        });
+        (mds, rc)
+    }
+
+    // round_idx \in [0; r_f - 1] indicates the round index of either first half full or second half full
+    pub fn full_round(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        is_first_half_full: bool,
+        round_idx: usize,
+        state_idx: usize,
+        state: &[AssignedCell<F, F>; T],
+    ) -> Result<AssignedCell<F, F>, Error> {
+        let mut state_vals = [Value::known(F::ZERO); T];
+        let q_1_vals = [F::ZERO; T];
+        let mut q_5_vals = [F::ZERO; T];
+        let q_o_val = -F::ONE;
 
-        let mut rc_val = F::ZERO;
-        for (j, (mij, cj)) in mds_row.iter().zip(rcs).enumerate() {
-            rc_val += *mij * cj;
+        let (mds, rc) = self.full_round_constants(is_first_half_full, round_idx);
+        let rc_val = rc[state_idx];
+        for (j, mij) in mds[state_idx].iter().enumerate() {
             q_5_vals[j] = *mij;
             ctx.assign_fixed(
                 || format!("full_round {}: q_5", round_idx),
@@ -159,7 +269,7 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
             self.main_gate.config().q_o,
             q_o_val,
         )?;
-        let out_val = Self::next_state_val(state_vals, q_1_vals, q_5_vals, q_o_val, rc_val);
+        let out_val = self.next_state_val(state_vals, q_1_vals, q_5_vals, q_o_val, rc_val);
         let out = ctx.assign_advice(
             || format!("full_round {}: out", round_idx),
             self.main_gate.config().out,
@@ -241,7 +351,7 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
             )?;
         }
 
-        let out_val = Self::next_state_val(state_vals, q_1_vals, q_5_vals, -F::ONE, rc_val);
+        let out_val = self.next_state_val(state_vals, q_1_vals, q_5_vals, -F::ONE, rc_val);
         ctx.assign_fixed(
             || format!("full_round {}: q_o", round_idx),
             self.main_gate.config().q_o,
@@ -256,27 +366,51 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
         Ok(out)
     }
 
-    pub fn permutation(
-        &self,
-        ctx: &mut RegionCtx<'_, F>,
-        inputs: Vec<F>,
-        init_state: &[AssignedValue<F>; T],
-    ) -> Result<[AssignedValue<F>; T], Error> {
-        let mut state = Vec::new();
-        for i in 0..T {
-            let si = self.pre_round(ctx, inputs.clone(), i, init_state)?;
-            state.push(si);
+    // sparse matrix and constants of a partial round, see `Round::Partial`
+    fn partial_round_constants(&self, round_idx: usize) -> Round<F, T> {
+        let c = self.spec.constants().partial()[round_idx];
+        let sparse_mds = &self.spec.mds_matrices().sparse_matrices()[round_idx];
+        let row = sparse_mds.row();
+        let mut col = [row[0]; T];
+        col[1..].copy_from_slice(sparse_mds.col_hat());
+        Round::Partial {
+            row: *row,
+            col,
+            // rc[0] = row[0] * c since col[0] = row[0]
+            rc: col.map(|m| m * c),
         }
+    }
 
+    // every round of the permutation following the pre-round
+    fn rounds(&self) -> Vec<Round<F, T>> {
         let r_f = self.spec.r_f() / 2;
         let r_p = self.spec.constants().partial().len();
+        let full = |is_first_half_full| {
+            (0..r_f).map(move |round_idx| {
+                let (mds, rc) = self.full_round_constants(is_first_half_full, round_idx);
+                Round::Full { mds, rc }
+            })
+        };
+        full(true)
+            .chain((0..r_p).map(|round_idx| self.partial_round_constants(round_idx)))
+            .chain(full(false))
+            .collect()
+    }
 
+    // half of the full rounds, laid out on the main gate
+    fn full_rounds(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        is_first_half_full: bool,
+        mut state: Vec<AssignedValue<F>>,
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        let r_f = self.spec.r_f() / 2;
         for round_idx in 0..r_f {
             let mut next_state = Vec::new();
             for state_idx in 0..T {
                 let si = self.full_round(
                     ctx,
-                    true,
+                    is_first_half_full,
                     round_idx,
                     state_idx,
                     state[..].try_into().unwrap(),
@@ -285,6 +419,32 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
             }
             state = next_state;
         }
+        Ok(state)
+    }
+
+    /// Permutes `init_state` (or the initial state) with `inputs` added to its rate part.
+    ///
+    /// On the main gate every round takes `T` rows, with a round gate every round after the
+    /// pre-round takes a single row.
+    pub fn permutation(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: Vec<WrapValue<F>>,
+        init_state: Option<&[AssignedValue<F>; T]>,
+    ) -> Result<[AssignedValue<F>; T], Error> {
+        let mut state = Vec::new();
+        for i in 0..T {
+            let si = self.pre_round(ctx, inputs.clone(), i, init_state)?;
+            state.push(si);
+        }
+
+        if let Some(round_gate) = &self.round_gate {
+            return round_gate.rounds(ctx, &self.rounds(), state[..].try_into().unwrap());
+        }
+
+        let r_p = self.spec.constants().partial().len();
+
+        state = self.full_rounds(ctx, true, state)?;
 
         for round_idx in 0..r_p {
             let mut next_state = Vec::new();
@@ -296,67 +456,314 @@ impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonChip<F, T, RATE>
             state = next_state;
         }
 
-        for round_idx in 0..r_f {
-            let mut next_state = Vec::new();
-            for state_idx in 0..T {
-                let si = self.full_round(
-                    ctx,
-                    false,
-                    round_idx,
-                    state_idx,
-                    state[..].try_into().unwrap(),
-                )?;
-                next_state.push(si);
-            }
-            state = next_state;
-        }
+        state = self.full_rounds(ctx, false, state)?;
         let res: [AssignedValue<F>; T] = state.try_into().unwrap();
         Ok(res)
     }
 
+    /// Absorbs raw field values, each one is assigned as a fresh witness in the `input` column
     pub fn update(&mut self, inputs: Vec<F>) {
+        self.buf.extend(
+            inputs
+                .into_iter()
+                .map(|input| WrapValue::Unassigned(Value::known(input))),
+        )
+    }
+
+    /// Absorbs already assigned cells, each one is copy-constrained into the `input` column
+    pub fn update_assigned(&mut self, inputs: &[AssignedValue<F>]) {
+        self.buf.extend(inputs.iter().map(WrapValue::from))
+    }
+
+    /// Absorbs a mix of assigned and unassigned values, [`WrapValue::Zero`] costs no cell at all
+    pub fn update_wrapped(&mut self, inputs: Vec<WrapValue<F>>) {
         self.buf.extend(inputs)
     }
 
+    /// Hashes exactly `L` elements in [`Domain::ConstantLength`], see
+    /// [`crate::poseidon_hash::PoseidonHash::hash`]. The layout only depends on `L` so that it
+    /// is the same whether the values are known or not, the zeros completing the last block
+    /// cost no cell.
+    pub fn hash<const L: usize>(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: [WrapValue<F>; L],
+    ) -> Result<AssignedValue<F>, Error> {
+        assert!(
+            self.buf.is_empty() && self.state.is_none() && self.domain == Domain::default(),
+            "hash needs a fresh sponge, its domain is set from L"
+        );
+        self.domain = Domain::ConstantLength {
+            len: L,
+            output_len: 1,
+        };
+
+        let mut blocks = inputs.to_vec();
+        blocks.resize(L.max(1).div_ceil(self.rate) * self.rate, WrapValue::Zero);
+        for block in blocks.chunks(self.rate) {
+            self.absorb_block(ctx, block.to_vec())?;
+        }
+        let state = self.state.as_ref().expect("a permutation was applied");
+        Ok(state[1].clone())
+    }
+
+    /// Compresses `RATE` elements with a single permutation, see
+    /// [`crate::poseidon_hash::PoseidonHash::compress`]
+    ///
+    /// The sponge itself is left untouched, so one chip can compress every node of a tree.
+    pub fn compress(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: [WrapValue<F>; RATE],
+    ) -> Result<AssignedValue<F>, Error> {
+        assert_eq!(self.rate, RATE, "compression absorbs the whole rate");
+        let state = self.permutation(ctx, inputs.to_vec(), None)?;
+        Ok(state[1].clone())
+    }
+
+    /// Absorbs the buffered elements with padding and returns the first element of the rate
     pub fn squeeze(&mut self, ctx: &mut RegionCtx<'_, F>) -> Result<AssignedValue<F>, Error> {
-        let buf = self.buf.clone();
-        let exact = buf.len() % RATE == 0;
+        Ok(self.squeeze_n(ctx, 1)?.remove(0))
+    }
+
+    /// Absorbs the buffered elements with padding and squeezes `n` elements: the rate of the
+    /// padded permutation is read first and, while more are needed, the sponge is permuted
+    /// again over an empty padded block. Elements of the rate left unread are discarded,
+    /// which matches [`crate::poseidon_hash::PoseidonHash::output_n`]
+    pub fn squeeze_n(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        n: usize,
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        let buf = mem::take(&mut self.buf);
+        let exact = buf.len() % self.rate == 0;
+
+        for chunk in buf.chunks(self.rate) {
+            self.absorb_block(ctx, chunk.to_vec())?;
+        }
+        if exact {
+            self.absorb_block(ctx, Vec::new())?;
+        }
+
+        let mut output = Vec::with_capacity(n);
+        loop {
+            let state = self
+                .state
+                .as_ref()
+                .expect("at least one permutation was applied");
+            output.extend(
+                state
+                    .iter()
+                    .skip(1)
+                    .take(self.rate.min(n - output.len()))
+                    .cloned(),
+            );
+            if output.len() == n {
+                return Ok(output);
+            }
+            self.absorb_block(ctx, Vec::new())?;
+        }
+    }
+
+    /// Squeezes an element and truncates it to its `num_bits` least significant bits,
+    /// returns the little-endian bit cells and the recomposed truncated value
+    #[allow(clippy::type_complexity)]
+    pub fn squeeze_n_bits(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        num_bits: usize,
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        self.squeeze_n_bits_with(ctx, num_bits, repr_bit)
+    }
 
-        let mut state: [_; T] = self
+    // as `squeeze_n_bits`, with the bit witnesses of `MainGate::to_bits_with`
+    #[allow(clippy::type_complexity)]
+    fn squeeze_n_bits_with(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        num_bits: usize,
+        witness: impl Fn(&F, usize) -> bool,
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        assert!(num_bits > 0 && num_bits <= F::NUM_BITS as usize);
+        let output = self.squeeze(ctx)?;
+        let mut bits = self
             .main_gate
-            .config()
-            .state
+            .to_bits_canonical_with(ctx, &output, witness)?;
+        bits.truncate(num_bits);
+        let truncated = self.main_gate.from_bits(ctx, &bits)?;
+        Ok((bits, truncated))
+    }
+
+    /// Absorbs `inputs[..len]` with padding and squeezes an element, as [`Self::update_assigned`]
+    /// followed by [`Self::squeeze`] would, while the layout only depends on `inputs.len()`,
+    /// the maximum length: `inputs[len..]` may hold any value.
+    ///
+    /// `len` is constrained to `0..=inputs.len()` through a one-hot vector `eq`, from which the
+    /// padded elements are `x_i * (len > i) + (len == i)`. All `inputs.len() / rate + 1`
+    /// permutations are applied and the state after the last one of `len` is selected.
+    pub fn squeeze_var_len(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: &[AssignedValue<F>],
+        len: &AssignedValue<F>,
+    ) -> Result<AssignedValue<F>, Error> {
+        assert!(self.buf.is_empty(), "the buffer must be squeezed first");
+        let max_len = inputs.len();
+        let num_blocks = max_len / self.rate + 1;
+
+        // eq[i] = (len == i)
+        let eq = (0..=max_len)
+            .map(|i| {
+                let bit = len.value().map(|len| {
+                    if *len == F::from(i as u64) {
+                        F::ONE
+                    } else {
+                        F::ZERO
+                    }
+                });
+                self.main_gate.assign_bit(ctx, bit)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let terms = eq.iter().map(|eq| (F::ONE, eq.clone())).collect::<Vec<_>>();
+        let sum = self.main_gate.linear_combination(ctx, &terms, -F::ONE)?;
+        self.main_gate.assert_zero(ctx, &sum)?;
+        let terms = eq
             .iter()
-            .zip(poseidon::State::<F, T>::default().words().iter())
-            .map(|(col, val)| ctx.assign_advice(|| "initial state", *col, Value::known(*val)))
-            .collect::<Result<Vec<_>, _>>()?
-            .try_into()
-            .expect("Safe, because zip two arrays");
-
-        for chunk in buf.chunks(RATE) {
-            let next_state = self.permutation(ctx, chunk.to_vec(), &state)?;
-            state = next_state;
+            .enumerate()
+            .map(|(i, eq)| (F::from(i as u64), eq.clone()))
+            .collect::<Vec<_>>();
+        let index = self.main_gate.linear_combination(ctx, &terms, F::ZERO)?;
+        ctx.constrain_equal(index.cell(), len.cell())?;
+
+        // lt[i] = (len > i) = sum_{j > i}(eq[j])
+        let mut lt = vec![eq[max_len].clone()];
+        for i in (1..max_len).rev() {
+            let prev = lt.last().expect("lt is not empty").clone();
+            lt.push(self.main_gate.linear_combination(
+                ctx,
+                &[(F::ONE, prev), (F::ONE, eq[i].clone())],
+                F::ZERO,
+            )?);
         }
+        lt.reverse();
 
-        if exact {
-            let next_state = self.permutation(ctx, Vec::new(), &state)?;
-            state = next_state;
+        let mut padded = Vec::with_capacity(num_blocks * self.rate);
+        for ((input, lt), eq) in inputs.iter().zip(lt.iter()).zip(eq.iter()) {
+            padded.push(WrapValue::Assigned(
+                self.main_gate.mul_add(ctx, input, lt, eq)?,
+            ));
         }
+        padded.push(WrapValue::Assigned(eq[max_len].clone()));
+        padded.resize(num_blocks * self.rate, WrapValue::Zero);
 
-        Ok(state[1].clone())
+        let mut states = Vec::with_capacity(num_blocks);
+        for block in padded.chunks(self.rate) {
+            self.absorb_block(ctx, block.to_vec())?;
+            states.push(self.state.clone().expect("a permutation was applied"));
+        }
+
+        // the last permutation of `len` is the one of block `len / rate`
+        let selectors = (0..num_blocks)
+            .map(|k| {
+                let terms = eq
+                    .iter()
+                    .skip(k * self.rate)
+                    .take(self.rate)
+                    .map(|eq| (F::ONE, eq.clone()))
+                    .collect::<Vec<_>>();
+                self.main_gate.linear_combination(ctx, &terms, F::ZERO)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut selected = Vec::with_capacity(T);
+        for i in 0..T {
+            let mut acc = self.main_gate.mul(ctx, &selectors[0], &states[0][i])?;
+            for (selector, state) in selectors.iter().zip(states.iter()).skip(1) {
+                acc = self.main_gate.mul_add(ctx, selector, &state[i], &acc)?;
+            }
+            selected.push(acc);
+        }
+        let state: [AssignedValue<F>; T] = selected.try_into().unwrap();
+        let output = state[1].clone();
+        self.state = Some(state);
+        Ok(output)
+    }
+
+    fn absorb_block(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        inputs: Vec<WrapValue<F>>,
+    ) -> Result<(), Error> {
+        let next_state = self.permutation(ctx, inputs, self.state.as_ref())?;
+        self.state = Some(next_state);
+        Ok(())
+    }
+}
+
+impl<C, F, const T: usize, const RATE: usize> ROCircuitTrait<C> for PoseidonChip<F, T, RATE>
+where
+    C: CurveAffine<ScalarExt = F>,
+    F: PrimeField + FromUniformBytes<64>,
+{
+    type Config = PoseidonConfig<T>;
+    type Constants = PoseidonSpec<F, T, RATE>;
+
+    fn new(config: Self::Config, constants: Self::Constants) -> Self {
+        PoseidonChip::new(config, constants)
+    }
+
+    fn absorb_scalar(&mut self, scalar: WrapValue<F>) -> &mut Self {
+        self.buf.push(scalar);
+        self
+    }
+
+    fn absorb_native_point(&mut self, point: &AssignedPoint<F>) -> &mut Self {
+        self.update_assigned(&[point.x.clone(), point.y.clone()]);
+        self
+    }
+
+    fn absorb_base(&mut self, base: &AssignedBase<F>) -> &mut Self {
+        self.update_assigned(&base.limbs);
+        self
+    }
+
+    fn absorb_point(&mut self, point: &AssignedBasePoint<F>) -> &mut Self {
+        self.update_assigned(&point.x.limbs);
+        self.update_assigned(&point.y.limbs);
+        self
+    }
+
+    fn squeeze(&mut self, ctx: &mut RegionCtx<'_, F>) -> Result<AssignedValue<F>, Error> {
+        PoseidonChip::squeeze(self, ctx)
+    }
+
+    fn squeeze_n(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        n: usize,
+    ) -> Result<Vec<AssignedValue<F>>, Error> {
+        PoseidonChip::squeeze_n(self, ctx, n)
+    }
+
+    fn squeeze_n_bits(
+        &mut self,
+        ctx: &mut RegionCtx<'_, F>,
+        num_bits: usize,
+    ) -> Result<(Vec<AssignedValue<F>>, AssignedValue<F>), Error> {
+        PoseidonChip::squeeze_n_bits(self, ctx, num_bits)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use ff::Field;
     use halo2_proofs::{
         circuit::{Layouter, SimpleFloorPlanner},
         plonk::{Circuit, Column, ConstraintSystem, Instance},
     };
-    use halo2curves::{group::ff::FromUniformBytes, pasta::Fp};
+    use halo2curves::pasta::{EqAffine, Fp, Fq};
 
     use super::*;
-    use crate::main_gate::MainGateConfig;
+    use crate::main_gate::{MainGateConfig, SBox};
 
     const T: usize = 3;
     const RATE: usize = 2;
@@ -369,31 +776,79 @@ mod tests {
         instance: Column<Instance>,
     }
 
-    struct TestCircuit<F: PrimeField> {
-        inputs: Vec<F>,
+    fn configure_test_circuit<F: PrimeField>(meta: &mut ConstraintSystem<F>) -> TestCircuitConfig {
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
+        let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+        let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+        TestCircuitConfig { pconfig, instance }
     }
 
-    impl<F: PrimeField> TestCircuit<F> {
-        fn new(inputs: Vec<F>) -> Self {
-            Self { inputs }
+    // hashes `L` inputs with the sponge, as `crate::test_circuit::TestCircuit` does
+    struct TestCircuit<F: PrimeField, const L: usize> {
+        inputs: [Value<F>; L],
+    }
+
+    impl<F: PrimeField, const L: usize> TestCircuit<F, L> {
+        fn new(inputs: [F; L]) -> Self {
+            Self {
+                inputs: inputs.map(Value::known),
+            }
         }
     }
 
-    impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for TestCircuit<F> {
+    impl<F: PrimeField + FromUniformBytes<64>, const L: usize> Circuit<F> for TestCircuit<F, L> {
         type Config = TestCircuitConfig;
         type FloorPlanner = SimpleFloorPlanner;
 
         fn without_witnesses(&self) -> Self {
-            Self { inputs: Vec::new() }
+            Self {
+                inputs: [Value::unknown(); L],
+            }
         }
 
         fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
-            let instance = meta.instance_column();
-            meta.enable_equality(instance);
-            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
-            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
-            let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
-            Self::Config { pconfig, instance }
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<F>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<F, T, RATE>::new_unchecked(R_F, R_P);
+            let output = layouter.assign_region(
+                || "poseidon hash",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone());
+                    pchip.update_wrapped(self.inputs.map(WrapValue::from).to_vec());
+                    pchip.squeeze(ctx)
+                },
+            )?;
+            layouter.constrain_instance(output.cell(), config.instance, 0)?;
+            Ok(())
+        }
+    }
+
+    // hashes the inputs placed in instance rows `1..=num_inputs` and exposes the output at row 0
+    struct AssignedInputsCircuit {
+        num_inputs: usize,
+    }
+
+    impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for AssignedInputsCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                num_inputs: self.num_inputs,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+            configure_test_circuit(meta)
         }
 
         fn synthesize(
@@ -401,13 +856,25 @@ mod tests {
             config: Self::Config,
             mut layouter: impl Layouter<F>,
         ) -> Result<(), Error> {
-            let spec = Spec::<F, T, RATE>::new(R_F, R_P);
-            let mut pchip = PoseidonChip::new(config.pconfig, spec);
-            pchip.update(self.inputs.clone());
+            let spec = PoseidonSpec::<F, T, RATE>::new_unchecked(R_F, R_P);
             let output = layouter.assign_region(
                 || "poseidon hash",
                 |region| {
                     let ctx = &mut RegionCtx::new(region, 0);
+                    let mut inputs = Vec::new();
+                    for i in 0..self.num_inputs {
+                        let input = ctx.region.assign_advice_from_instance(
+                            || "input",
+                            config.instance,
+                            i + 1,
+                            config.pconfig.input,
+                            ctx.offset(),
+                        )?;
+                        inputs.push(input);
+                        ctx.next();
+                    }
+                    let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone());
+                    pchip.update_assigned(&inputs);
                     pchip.squeeze(ctx)
                 },
             )?;
@@ -417,14 +884,1188 @@ mod tests {
     }
 
     #[test]
-    fn test_mock() {
+    fn test_mock_assigned_inputs() {
         use halo2_proofs::dev::MockProver;
         const K: u32 = 10;
-        let mut inputs = Vec::new();
-        for i in 0..5 {
-            inputs.push(Fp::from(i as u64));
+        let out_hash = Fp::from_str_vartime(
+            "13037709793114148810823325920380362524528554380279235267325741570708489436263",
+        )
+        .unwrap();
+        let inputs = (0..5).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let circuit = AssignedInputsCircuit { num_inputs: 5 };
+
+        let public_inputs = vec![std::iter::once(out_hash).chain(inputs.clone()).collect()];
+        let prover = MockProver::run(K, &circuit, public_inputs).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // the output is bound to the copied inputs, so changing one of them must fail
+        let mut wrong_inputs = inputs;
+        wrong_inputs[2] = Fp::from(100u64);
+        let public_inputs = vec![std::iter::once(out_hash).chain(wrong_inputs).collect()];
+        let prover = MockProver::run(K, &circuit, public_inputs).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // absorbs the instance rows `1..=5` through the generic RO interface
+    struct ROCircuit;
+
+    fn absorb_and_squeeze<C: CurveAffine, RO: ROCircuitTrait<C>>(
+        ro: &mut RO,
+        ctx: &mut RegionCtx<'_, C::Scalar>,
+        cells: &[AssignedValue<C::Scalar>],
+    ) -> Result<AssignedValue<C::Scalar>, Error> {
+        ro.absorb_scalar(cells[0].clone().into())
+            .absorb_native_point(&AssignedPoint {
+                x: cells[1].clone(),
+                y: cells[2].clone(),
+            })
+            .absorb_scalar(cells[3].clone().into())
+            .absorb_scalar(cells[4].clone().into())
+            .squeeze(ctx)
+    }
+
+    impl Circuit<Fp> for ROCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let output = layouter.assign_region(
+                || "poseidon ro",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut cells = Vec::new();
+                    for i in 0..5 {
+                        let cell = ctx.region.assign_advice_from_instance(
+                            || "input",
+                            config.instance,
+                            i + 1,
+                            config.pconfig.input,
+                            ctx.offset(),
+                        )?;
+                        cells.push(cell);
+                        ctx.next();
+                    }
+                    let mut ro = <PoseidonChip<Fp, T, RATE> as ROCircuitTrait<EqAffine>>::new(
+                        config.pconfig.clone().into(),
+                        spec.clone(),
+                    );
+                    absorb_and_squeeze::<EqAffine, _>(&mut ro, ctx, &cells)
+                },
+            )?;
+            layouter.constrain_instance(output.cell(), config.instance, 0)?;
+            Ok(())
         }
-        let circuit = TestCircuit::new(inputs);
+    }
+
+    #[test]
+    fn test_mock_ro_circuit() {
+        use halo2_proofs::dev::MockProver;
+        const K: u32 = 10;
+        let out_hash = Fp::from_str_vartime(
+            "13037709793114148810823325920380362524528554380279235267325741570708489436263",
+        )
+        .unwrap();
+        let public_inputs = vec![std::iter::once(out_hash)
+            .chain((0..5).map(|i| Fp::from(i as u64)))
+            .collect()];
+        let prover = MockProver::run(K, &ROCircuit, public_inputs).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    // absorbs `0..5` and squeezes three elements, absorbs `7` and squeezes one, then squeezes
+    // four more; the outputs are exposed in the instance column
+    struct DuplexCircuit;
+
+    impl Circuit<Fp> for DuplexCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let outputs = layouter.assign_region(
+                || "poseidon duplex",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone());
+                    pchip.update((0..5).map(|i| Fp::from(i as u64)).collect());
+                    let mut outputs = pchip.squeeze_n(ctx, 3)?;
+                    pchip.update(vec![Fp::from(7u64)]);
+                    outputs.push(pchip.squeeze(ctx)?);
+                    outputs.extend(pchip.squeeze_n(ctx, 4)?);
+                    Ok(outputs)
+                },
+            )?;
+            for (row, output) in outputs.iter().enumerate() {
+                layouter.constrain_instance(output.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_duplex() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 10;
+        let mut poseidon =
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P));
+        poseidon.absorb_scalars(&(0..5).map(|i| Fp::from(i as u64)).collect::<Vec<_>>());
+        let mut expected = poseidon.squeeze_n(3);
+        expected.push(poseidon.absorb_scalar(Fp::from(7u64)).squeeze());
+        expected.extend(poseidon.squeeze_n(4));
+
+        let prover = MockProver::run(K, &DuplexCircuit, vec![expected]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    // absorbs values of the base field of `EqAffine` as limbs, between two scalars
+    struct BaseCircuit {
+        bases: Vec<Value<Fq>>,
+    }
+
+    impl Circuit<Fp> for BaseCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                bases: vec![Value::unknown(); self.bases.len()],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let output = layouter.assign_region(
+                || "poseidon base absorption",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let main_gate = MainGate::new(config.pconfig.clone());
+                    let mut ro = <PoseidonChip<Fp, T, RATE> as ROCircuitTrait<EqAffine>>::new(
+                        config.pconfig.clone().into(),
+                        spec.clone(),
+                    );
+                    ROCircuitTrait::<EqAffine>::absorb_scalar(
+                        &mut ro,
+                        Value::known(Fp::from(1u64)).into(),
+                    );
+                    for base in self.bases.iter() {
+                        let base = AssignedBase::assign(&main_gate, ctx, *base)?;
+                        ROCircuitTrait::<EqAffine>::absorb_base(&mut ro, &base);
+                    }
+                    ROCircuitTrait::<EqAffine>::absorb_scalar(
+                        &mut ro,
+                        Value::known(Fp::from(2u64)).into(),
+                    );
+                    ROCircuitTrait::<EqAffine>::squeeze(&mut ro, ctx)
+                },
+            )?;
+            layouter.constrain_instance(output.cell(), config.instance, 0)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_absorb_base() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 12;
+        // the largest base is above the modulus of the scalar field
+        let bases = [Fq::from(5u64), -Fq::from(1u64)];
+        let mut poseidon =
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P));
+        poseidon.absorb_scalar(Fp::from(1u64));
+        for base in bases.iter() {
+            poseidon.absorb_base(base);
+        }
+        let expected = poseidon.absorb_scalar(Fp::from(2u64)).squeeze();
+
+        let circuit = BaseCircuit {
+            bases: bases.iter().copied().map(Value::known).collect(),
+        };
+        let prover = MockProver::run(K, &circuit, vec![vec![expected]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        let prover = MockProver::run(K, &circuit, vec![vec![expected + Fp::from(1u64)]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // absorbs points of `EqAffine`, whose coordinates are in its base field
+    struct PointCircuit {
+        points: Vec<Value<EqAffine>>,
+    }
+
+    impl Circuit<Fp> for PointCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                points: vec![Value::unknown(); self.points.len()],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let output = layouter.assign_region(
+                || "poseidon point absorption",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let main_gate = MainGate::new(config.pconfig.clone());
+                    let mut ro = <PoseidonChip<Fp, T, RATE> as ROCircuitTrait<EqAffine>>::new(
+                        config.pconfig.clone().into(),
+                        spec.clone(),
+                    );
+                    for point in self.points.iter() {
+                        let point = AssignedBasePoint::assign(&main_gate, ctx, *point)?;
+                        ROCircuitTrait::<EqAffine>::absorb_point(&mut ro, &point);
+                    }
+                    ROCircuitTrait::<EqAffine>::squeeze(&mut ro, ctx)
+                },
+            )?;
+            layouter.constrain_instance(output.cell(), config.instance, 0)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_absorb_point() {
+        use halo2_proofs::dev::MockProver;
+        use halo2curves::group::prime::PrimeCurveAffine;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 12;
+        let points = [EqAffine::generator(), EqAffine::identity()];
+        let mut poseidon =
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P));
+        for point in points.iter() {
+            poseidon.absorb_point(point);
+        }
+        let expected = poseidon.squeeze();
+
+        // the identity is encoded as `(0, 0)`
+        let coordinates = points[0].coordinates().unwrap();
+        let mut poseidon =
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P));
+        poseidon
+            .absorb_base(coordinates.x())
+            .absorb_base(coordinates.y())
+            .absorb_base(&Fq::from(0u64))
+            .absorb_base(&Fq::from(0u64));
+        assert_eq!(poseidon.squeeze(), expected);
+
+        let circuit = PointCircuit {
+            points: points.iter().copied().map(Value::known).collect(),
+        };
+        let prover = MockProver::run(K, &circuit, vec![vec![expected]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+        let prover = MockProver::run(K, &circuit, vec![vec![expected + Fp::from(1u64)]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // squeezes a 128-bit challenge after absorbing `0..5`
+    struct SqueezeBitsCircuit;
+
+    impl Circuit<Fp> for SqueezeBitsCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let (bits, truncated) = layouter.assign_region(
+                || "poseidon squeeze bits",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone());
+                    pchip.update((0..5).map(|i| Fp::from(i as u64)).collect());
+                    pchip.squeeze_n_bits(ctx, 128)
+                },
+            )?;
+            assert_eq!(bits.len(), 128);
+            layouter.constrain_instance(truncated.cell(), config.instance, 0)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_squeeze_n_bits() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 10;
+        let mut poseidon =
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P));
+        let challenge = poseidon
+            .absorb_scalars(&(0..5).map(|i| Fp::from(i as u64)).collect::<Vec<_>>())
+            .squeeze_n_bits(128);
+        assert!(challenge.to_repr().as_ref()[16..]
+            .iter()
+            .all(|byte| *byte == 0));
+
+        let prover = MockProver::run(K, &SqueezeBitsCircuit, vec![vec![challenge]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    // the bits of `output + p`, i.e. a non-canonical decomposition of the squeezed output,
+    // when they fit in `Fp::NUM_BITS` bits
+    fn non_canonical_bits(output: &Fp) -> Option<Vec<bool>> {
+        let max = (-Fp::ONE).to_repr();
+        let mut sum = [0u8; 32];
+        let mut carry = 1u16;
+        for (i, (x, m)) in output.to_repr().iter().zip(max.iter()).enumerate() {
+            let s = *x as u16 + *m as u16 + carry;
+            sum[i] = s as u8;
+            carry = s >> 8;
+        }
+        let bits = (0..256)
+            .map(|i| (sum[i / 8] >> (i % 8)) & 1 == 1)
+            .collect::<Vec<_>>();
+        (carry == 0 && !bits[Fp::NUM_BITS as usize]).then(|| bits[..Fp::NUM_BITS as usize].to_vec())
+    }
+
+    // squeezes 128 bits of the hash of `input` through `PoseidonChip::squeeze_n_bits_with`,
+    // with the bits of `output + p` as the witness of the decomposition when `forge` is set
+    struct NonCanonicalBitsCircuit {
+        input: Fp,
+        forge: bool,
+    }
+
+    impl Circuit<Fp> for NonCanonicalBitsCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                input: self.input,
+                forge: self.forge,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let truncated = layouter.assign_region(
+                || "poseidon squeeze non-canonical bits",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone());
+                    pchip.update(vec![self.input]);
+                    let (_, truncated) = if self.forge {
+                        pchip.squeeze_n_bits_with(ctx, 128, |output, i| {
+                            non_canonical_bits(output).expect("the sum fits")[i]
+                        })?
+                    } else {
+                        pchip.squeeze_n_bits(ctx, 128)?
+                    };
+                    Ok(truncated)
+                },
+            )?;
+            layouter.constrain_instance(truncated.cell(), config.instance, 0)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_squeeze_n_bits_non_canonical() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 11;
+        let hash = |input: Fp| {
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P))
+                .absorb_scalars(&[input])
+                .squeeze()
+        };
+        // an input whose hash `x` has `x + p` below `2^Fp::NUM_BITS`, half of them do
+        let (input, bits) = (0u64..)
+            .find_map(|i| non_canonical_bits(&hash(Fp::from(i))).map(|bits| (Fp::from(i), bits)))
+            .unwrap();
+        let honest = Fp::from_u128(u128::from_le_bytes(
+            hash(input).to_repr()[..16].try_into().unwrap(),
+        ));
+        // the 128 least significant bits of `x + p` differ from the ones of `x`
+        let forged = bits[..128]
+            .iter()
+            .rev()
+            .fold(Fp::ZERO, |acc, bit| acc.double() + Fp::from(*bit as u64));
+
+        assert_ne!(forged, honest);
+
+        let circuit = NonCanonicalBitsCircuit {
+            input,
+            forge: false,
+        };
+        let prover = MockProver::run(K, &circuit, vec![vec![honest]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // the forged bits recompose to the output but are rejected as not canonical
+        let circuit = NonCanonicalBitsCircuit { input, forge: true };
+        let prover = MockProver::run(K, &circuit, vec![vec![forged]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[derive(Clone, Debug)]
+    struct RoundGateCircuitConfig {
+        pconfig: PoseidonConfig<T>,
+        instance: Column<Instance>,
+    }
+
+    // hashes `inputs` with the rounds laid out on the round gate, or on the main gate only
+    // when `use_round_gate` is unset, and records the number of rows used
+    struct RoundGateCircuit {
+        inputs: Vec<Fp>,
+        use_round_gate: bool,
+        spec: PoseidonSpec<Fp, T, RATE>,
+        rows: std::cell::Cell<usize>,
+    }
+
+    impl RoundGateCircuit {
+        fn new(inputs: Vec<Fp>, use_round_gate: bool) -> Self {
+            Self {
+                inputs,
+                use_round_gate,
+                spec: PoseidonSpec::new_unchecked(R_F, R_P),
+                rows: std::cell::Cell::new(0),
+            }
+        }
+    }
+
+    impl Circuit<Fp> for RoundGateCircuit {
+        type Config = RoundGateCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                spec: self.spec.clone(),
+                ..Self::new(self.inputs.clone(), self.use_round_gate)
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4 + T * T + T + 2]
+                .map(|_| meta.fixed_column())
+                .into_iter();
+            let main_gate_config = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            let round_gate_config = RoundGate::configure(meta, &main_gate_config, &mut fix_cols);
+            Self::Config {
+                pconfig: PoseidonConfig::RoundGate(main_gate_config, round_gate_config),
+                instance,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let output = layouter.assign_region(
+                || "poseidon hash",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let pconfig = match &config.pconfig {
+                        PoseidonConfig::RoundGate(main_gate_config, _) if !self.use_round_gate => {
+                            PoseidonConfig::MainGate(main_gate_config.clone())
+                        }
+                        pconfig => pconfig.clone(),
+                    };
+                    let mut pchip = PoseidonChip::new(pconfig, self.spec.clone());
+                    pchip.update(self.inputs.clone());
+                    let output = pchip.squeeze(ctx)?;
+                    self.rows.set(ctx.offset());
+                    Ok(output)
+                },
+            )?;
+            layouter.constrain_instance(output.cell(), config.instance, 0)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_round_gate() {
+        use halo2_proofs::dev::MockProver;
+        const K: u32 = 10;
+        let circuit = RoundGateCircuit::new((0..5).map(|i| Fp::from(i as u64)).collect(), true);
+        let out_hash = Fp::from_str_vartime(
+            "13037709793114148810823325920380362524528554380279235267325741570708489436263",
+        )
+        .unwrap();
+        let prover = MockProver::run(K, &circuit, vec![vec![out_hash]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_round_gate_rows() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 10;
+        // a single input, hence a single permutation
+        let inputs = vec![Fp::from(1u64)];
+        let out_hash =
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P))
+                .absorb_scalars(&inputs)
+                .squeeze();
+
+        let rows = |use_round_gate| {
+            let circuit = RoundGateCircuit::new(inputs.clone(), use_round_gate);
+            let prover = MockProver::run(K, &circuit, vec![vec![out_hash]]).unwrap();
+            assert_eq!(prover.verify(), Ok(()));
+            circuit.rows.get()
+        };
+        let main_gate_rows = rows(false);
+        let round_gate_rows = rows(true);
+        assert_eq!(main_gate_rows, T + (R_F + R_P) * T);
+        assert_eq!(round_gate_rows, T + R_F + R_P + 1);
+    }
+
+    // prints the rows and the proving time of hashes of several lengths with the recommended
+    // rounds, on the main gate only and with the round gate:
+    // `cargo test --release bench_round_gate -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_round_gate() {
+        use std::time::Instant;
+
+        use halo2_proofs::{
+            dev::MockProver,
+            plonk::{create_proof, keygen_pk, keygen_vk},
+            poly::{
+                commitment::ParamsProver,
+                ipa::{
+                    commitment::{IPACommitmentScheme, ParamsIPA},
+                    multiopen::ProverIPA,
+                },
+            },
+            transcript::{Blake2bWrite, Challenge255, TranscriptWriterBuffer},
+        };
+        use rand_core::OsRng;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const MAX_K: u32 = 14;
+        let spec = PoseidonSpec::<Fp, T, RATE>::new(8, 57);
+        println!("| inputs | gate | rows | k | proving time |");
+        println!("|-------:|------|-----:|--:|-------------:|");
+        for len in [1, 4, 16, 64] {
+            let inputs = (0..len).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+            let out_hash = PoseidonHash::<EqAffine, Fp, T, RATE>::new(spec.clone())
+                .absorb_scalars(&inputs)
+                .squeeze();
+            for use_round_gate in [false, true] {
+                let circuit = RoundGateCircuit {
+                    spec: spec.clone(),
+                    ..RoundGateCircuit::new(inputs.clone(), use_round_gate)
+                };
+                let prover = MockProver::run(MAX_K, &circuit, vec![vec![out_hash]]).unwrap();
+                assert_eq!(prover.verify(), Ok(()));
+                let rows = circuit.rows.get();
+                // the last rows of the circuit are left to the blinding factors
+                let k = (rows + 8).next_power_of_two().trailing_zeros();
+
+                let params = ParamsIPA::<EqAffine>::new(k);
+                let vk = keygen_vk(&params, &circuit).unwrap();
+                let pk = keygen_pk(&params, vk, &circuit).unwrap();
+                let start = Instant::now();
+                let mut transcript = Blake2bWrite::<_, EqAffine, Challenge255<_>>::init(vec![]);
+                create_proof::<IPACommitmentScheme<_>, ProverIPA<'_, _>, _, _, _, _>(
+                    &params,
+                    &pk,
+                    &[circuit],
+                    &[&[&[out_hash]]],
+                    OsRng,
+                    &mut transcript,
+                )
+                .unwrap();
+                let gate = if use_round_gate { "round" } else { "main" };
+                println!("| {len} | {gate} | {rows} | {k} | {:?} |", start.elapsed());
+            }
+        }
+    }
+
+    // `x^7` needs more rounds than `R_F` and `R_P`
+    const SBOX_R_F: usize = 8;
+    const SBOX_R_P: usize = 57;
+
+    // hashes `inputs` as `RoundGateCircuit` does, with `x^7` as the S-box of both gates
+    struct SBoxCircuit {
+        inputs: Vec<Fp>,
+        use_round_gate: bool,
+    }
+
+    impl Circuit<Fp> for SBoxCircuit {
+        type Config = RoundGateCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                inputs: self.inputs.clone(),
+                use_round_gate: self.use_round_gate,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4 + T * T + T + 2]
+                .map(|_| meta.fixed_column())
+                .into_iter();
+            let main_gate_config =
+                MainGate::configure_with_sbox(meta, &mut adv_cols, &mut fix_cols, SBox::Pow7);
+            let round_gate_config = RoundGate::configure(meta, &main_gate_config, &mut fix_cols);
+            Self::Config {
+                pconfig: PoseidonConfig::RoundGate(main_gate_config, round_gate_config),
+                instance,
+            }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            RoundGateCircuit {
+                spec: PoseidonSpec::new(SBOX_R_F, SBOX_R_P).with_sbox(SBox::Pow7),
+                ..RoundGateCircuit::new(self.inputs.clone(), self.use_round_gate)
+            }
+            .synthesize(config, layouter)
+        }
+    }
+
+    #[test]
+    fn test_mock_sbox() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 10;
+        let inputs = (0..5).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let spec = PoseidonSpec::<Fp, T, RATE>::new(SBOX_R_F, SBOX_R_P);
+        let out_hash = PoseidonHash::<EqAffine, Fp, T, RATE>::new(spec.clone())
+            .absorb_scalars(&inputs)
+            .squeeze();
+        let out_hash_pow7 = PoseidonHash::<EqAffine, Fp, T, RATE>::new(spec.with_sbox(SBox::Pow7))
+            .absorb_scalars(&inputs)
+            .squeeze();
+        assert_ne!(out_hash, out_hash_pow7);
+
+        for use_round_gate in [false, true] {
+            let circuit = SBoxCircuit {
+                inputs: inputs.clone(),
+                use_round_gate,
+            };
+            let prover = MockProver::run(K, &circuit, vec![vec![out_hash_pow7]]).unwrap();
+            assert_eq!(prover.verify(), Ok(()));
+            let prover = MockProver::run(K, &circuit, vec![vec![out_hash]]).unwrap();
+            assert!(prover.verify().is_err());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "the S-boxes of the gate and the spec differ")]
+    fn test_sbox_mismatch() {
+        let mut meta = ConstraintSystem::<Fp>::default();
+        let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+        let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+        let config = MainGate::<Fp, T>::configure_with_sbox(
+            &mut meta,
+            &mut adv_cols,
+            &mut fix_cols,
+            SBox::Pow7,
+        );
+        PoseidonChip::new(config, PoseidonSpec::<Fp, T, RATE>::new(SBOX_R_F, SBOX_R_P));
+    }
+
+    // hashes the first `len` of `max_len` inputs, `len` and the inputs being copied from
+    // instance rows `1` and `2..2 + max_len`, and exposes the output at row 0
+    struct VarLenCircuit {
+        max_len: usize,
+    }
+
+    impl Circuit<Fp> for VarLenCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                max_len: self.max_len,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let output = layouter.assign_region(
+                || "poseidon hash",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut cells = Vec::new();
+                    for i in 0..self.max_len + 1 {
+                        cells.push(ctx.region.assign_advice_from_instance(
+                            || "len and inputs",
+                            config.instance,
+                            i + 1,
+                            config.pconfig.input,
+                            ctx.offset(),
+                        )?);
+                        ctx.next();
+                    }
+                    let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone());
+                    pchip.squeeze_var_len(ctx, &cells[1..], &cells[0])
+                },
+            )?;
+            layouter.constrain_instance(output.cell(), config.instance, 0)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_var_len() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 10;
+        const MAX_LEN: usize = 5;
+        let inputs = (0..MAX_LEN)
+            .map(|i| Fp::from(i as u64 + 1))
+            .collect::<Vec<_>>();
+        let circuit = VarLenCircuit { max_len: MAX_LEN };
+        let public_inputs = |output: Fp, len: usize| {
+            vec![[output, Fp::from(len as u64)]
+                .into_iter()
+                .chain(inputs.iter().copied())
+                .collect::<Vec<_>>()]
+        };
+
+        for len in 0..=MAX_LEN {
+            let output =
+                PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P))
+                    .absorb_scalars(&inputs[..len])
+                    .squeeze();
+            let prover = MockProver::run(K, &circuit, public_inputs(output, len)).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "len = {}", len);
+
+            // the elements beyond `len` are not hashed
+            let wrong_len = (len + 1) % (MAX_LEN + 1);
+            let prover = MockProver::run(K, &circuit, public_inputs(output, wrong_len)).unwrap();
+            assert!(prover.verify().is_err(), "len = {}", len);
+        }
+
+        // a length beyond the maximum cannot be selected
+        let output =
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P))
+                .absorb_scalars(&inputs)
+                .squeeze();
+        let prover = MockProver::run(K, &circuit, public_inputs(output, MAX_LEN + 1)).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // hashes the inputs placed in instance rows `1..=2` in `domain`
+    struct DomainCircuit {
+        domain: Domain,
+    }
+
+    impl Circuit<Fp> for DomainCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                domain: self.domain,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let output = layouter.assign_region(
+                || "poseidon hash",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut inputs = Vec::new();
+                    for i in 0..2 {
+                        inputs.push(ctx.region.assign_advice_from_instance(
+                            || "input",
+                            config.instance,
+                            i + 1,
+                            config.pconfig.input,
+                            ctx.offset(),
+                        )?);
+                        ctx.next();
+                    }
+                    let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone())
+                        .with_domain(self.domain);
+                    pchip.update_assigned(&inputs);
+                    pchip.squeeze(ctx)
+                },
+            )?;
+            layouter.constrain_instance(output.cell(), config.instance, 0)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_domain() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 10;
+        let inputs = [Fp::from(1u64), Fp::from(2u64)];
+        for domain in [
+            Domain::default(),
+            Domain::ConstantLength {
+                len: 2,
+                output_len: 1,
+            },
+            Domain::MerkleTree { arity: 2 },
+            Domain::Custom { identifier: 1 },
+        ] {
+            let mut poseidon =
+                PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P))
+                    .with_domain(domain);
+            poseidon.update(&inputs);
+            let output = poseidon.output();
+            let circuit = DomainCircuit { domain };
+            let public_inputs = vec![[output].into_iter().chain(inputs).collect()];
+            let prover = MockProver::run(K, &circuit, public_inputs).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "{:?}", domain);
+        }
+    }
+
+    // compresses the inputs placed in instance rows `1..=2`, then the result with the first
+    // input, and exposes both nodes at rows 0 and 3
+    struct CompressCircuit;
+
+    impl Circuit<Fp> for CompressCircuit {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let (node, parent) = layouter.assign_region(
+                || "poseidon compression",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut inputs = Vec::new();
+                    for i in 0..2 {
+                        inputs.push(ctx.region.assign_advice_from_instance(
+                            || "input",
+                            config.instance,
+                            i + 1,
+                            config.pconfig.input,
+                            ctx.offset(),
+                        )?);
+                        ctx.next();
+                    }
+                    let pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone())
+                        .with_domain(Domain::MerkleTree { arity: 2 });
+                    let node = pchip.compress(ctx, [(&inputs[0]).into(), (&inputs[1]).into()])?;
+                    let parent = pchip.compress(ctx, [(&node).into(), (&inputs[0]).into()])?;
+                    Ok((node, parent))
+                },
+            )?;
+            layouter.constrain_instance(node.cell(), config.instance, 0)?;
+            layouter.constrain_instance(parent.cell(), config.instance, 3)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_compress() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 10;
+        let (left, right) = (Fp::from(1u64), Fp::from(2u64));
+        let poseidon =
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P))
+                .with_domain(Domain::MerkleTree { arity: 2 });
+        let node = poseidon.compress([left, right]);
+        let parent = poseidon.compress([node, left]);
+
+        let prover =
+            MockProver::run(K, &CompressCircuit, vec![vec![node, left, right, parent]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[derive(Clone, Debug)]
+    struct RateCircuitConfig<const T: usize> {
+        pconfig: MainGateConfig<T>,
+        instance: Column<Instance>,
+    }
+
+    // absorbs `inputs` with a sponge of rate `rate` and exposes three squeezed elements
+    struct RateCircuit<const T: usize, const RATE: usize> {
+        inputs: Vec<Fp>,
+        rate: usize,
+    }
+
+    impl<const T: usize, const RATE: usize> Circuit<Fp> for RateCircuit<T, RATE> {
+        type Config = RateCircuitConfig<T>;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                inputs: self.inputs.clone(),
+                rate: self.rate,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = (0..T + 2)
+                .map(|_| meta.advice_column())
+                .collect::<Vec<_>>()
+                .into_iter();
+            let mut fix_cols = (0..2 * T + 4)
+                .map(|_| meta.fixed_column())
+                .collect::<Vec<_>>()
+                .into_iter();
+            let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            Self::Config { pconfig, instance }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let output = layouter.assign_region(
+                || "poseidon hash",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone())
+                        .with_rate(self.rate);
+                    pchip.update(self.inputs.clone());
+                    pchip.squeeze_n(ctx, 3)
+                },
+            )?;
+            for (row, output) in output.iter().enumerate() {
+                layouter.constrain_instance(output.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn check_rate<const T: usize, const RATE: usize>(rate: usize) {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::{poseidon_hash::PoseidonHash, ro_types::ROTrait};
+
+        const K: u32 = 10;
+        let inputs = (0..5).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let expected =
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P))
+                .with_rate(rate)
+                .absorb_scalars(&inputs)
+                .squeeze_n(3);
+
+        let circuit = RateCircuit::<T, RATE> { inputs, rate };
+        let prover = MockProver::run(K, &circuit, vec![expected]).unwrap();
+        assert_eq!(prover.verify(), Ok(()), "T = {}, rate = {}", T, rate);
+    }
+
+    #[test]
+    fn test_mock_rate() {
+        check_rate::<3, 2>(1);
+        check_rate::<3, 2>(2);
+        check_rate::<4, 3>(1);
+        check_rate::<4, 3>(2);
+        check_rate::<5, 4>(2);
+        check_rate::<5, 4>(4);
+    }
+
+    // hashes `L` inputs in the constant-length domain
+    struct ConstantLengthCircuit<const L: usize> {
+        inputs: [Value<Fp>; L],
+    }
+
+    impl<const L: usize> Circuit<Fp> for ConstantLengthCircuit<L> {
+        type Config = TestCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                inputs: [Value::unknown(); L],
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            configure_test_circuit(meta)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let output = layouter.assign_region(
+                || "constant-length hash",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone());
+                    pchip.hash(ctx, self.inputs.map(WrapValue::from))
+                },
+            )?;
+            layouter.constrain_instance(output.cell(), config.instance, 0)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_constant_length() {
+        use halo2_proofs::dev::MockProver;
+
+        use crate::poseidon_hash::PoseidonHash;
+
+        const K: u32 = 10;
+        let inputs = std::array::from_fn::<_, 5, _>(|i| Fp::from(i as u64));
+        let output =
+            PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new_unchecked(R_F, R_P))
+                .hash(inputs);
+        let circuit = ConstantLengthCircuit {
+            inputs: inputs.map(Value::known),
+        };
+        let prover = MockProver::run(K, &circuit, vec![vec![output]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let prover = MockProver::run(K, &circuit, vec![vec![output + Fp::ONE]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_mock_test_circuit() {
+        use halo2_proofs::dev::MockProver;
+        use halo2curves::bn256::Fr;
+
+        use crate::test_circuit::{TestCircuit, MAX_INPUT_LEN};
+
+        const K: u32 = 10;
+        let circuit = TestCircuit::new(std::array::from_fn::<_, 5, _>(|i| Fr::from(i as u64)));
+        // the sponge vector of `poseidon_hash::tests::test_poseidon_hash`
+        let out_hash = Fr::from_str_vartime(
+            "20304616028358001435806807494046171997958789835068077254356069730773893150537",
+        )
+        .unwrap();
+        let prover = MockProver::run(K, &circuit, vec![vec![out_hash]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        let prover = MockProver::run(K, &circuit, vec![vec![out_hash + Fr::from(1u64)]]).unwrap();
+        assert!(prover.verify().is_err());
+
+        // the layout of `MAX_INPUT_LEN` inputs is the largest fitting in `2^K` rows
+        let circuit = TestCircuit::new([Fr::from(1u64); MAX_INPUT_LEN]);
+        assert!(MockProver::run(K, &circuit, vec![vec![out_hash]]).is_ok());
+        let circuit = TestCircuit::new([Fr::from(1u64); MAX_INPUT_LEN + 1]);
+        assert!(MockProver::run(K, &circuit, vec![vec![out_hash]]).is_err());
+    }
+
+    #[test]
+    fn test_mock() {
+        use halo2_proofs::dev::MockProver;
+        const K: u32 = 10;
+        let circuit = TestCircuit::new(std::array::from_fn::<_, 5, _>(|i| Fp::from(i as u64)));
         // hex = 0x1cd3150d8e12454ff385da8a4d864af6d0f021529207b16dd6c3d8f2b52cfc67
         let out_hash = Fp::from_str_vartime(
             "13037709793114148810823325920380362524528554380279235267325741570708489436263",
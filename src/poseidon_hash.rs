@@ -4,8 +4,60 @@ use std::{iter, marker::PhantomData, mem};
 use halo2_proofs::arithmetic::CurveAffine;
 use halo2curves::group::ff::{FromUniformBytes, PrimeField};
 use poseidon::{SparseMDSMatrix, Spec};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::ro_types::{ROConstantsTrait, ROTrait};
+use crate::{
+    main_gate::SBox,
+    ro_types::{base_to_limbs, point_coordinates, ROConstantsTrait, ROTrait},
+};
+
+/// Domain separation of the sponge, encoded in the capacity element of the initial state
+/// following section 4.2 of the [Poseidon paper](https://eprint.iacr.org/2019/458.pdf)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Domain {
+    /// Hashing of any number of elements into `output_len` ones, `2^64 + (output_len - 1)`
+    VariableLength { output_len: usize },
+    /// Hashing of exactly `len` elements into `output_len` ones, `len * 2^64 + (output_len - 1)`
+    ConstantLength { len: usize, output_len: usize },
+    /// Compression of the children of a node of a Merkle tree with `arity` children,
+    /// `2^arity - 1`
+    MerkleTree { arity: usize },
+    /// Any other use, `identifier * 2^32`
+    Custom { identifier: u64 },
+}
+
+impl Default for Domain {
+    /// The domain of `poseidon::State::default`
+    fn default() -> Self {
+        Domain::VariableLength { output_len: 1 }
+    }
+}
+
+impl Domain {
+    pub fn capacity<F: PrimeField>(&self) -> F {
+        let two_32 = F::from(1u64 << 32);
+        let two_64 = two_32.square();
+        match *self {
+            Domain::VariableLength { output_len } => {
+                assert!(output_len > 0);
+                two_64 + F::from(output_len as u64 - 1)
+            }
+            Domain::ConstantLength { len, output_len } => {
+                assert!(output_len > 0);
+                F::from(len as u64) * two_64 + F::from(output_len as u64 - 1)
+            }
+            Domain::MerkleTree { arity } => F::from(2u64).pow_vartime([arity as u64]) - F::ONE,
+            Domain::Custom { identifier } => F::from(identifier) * two_32,
+        }
+    }
+
+    /// The initial state of a sponge in this domain, the capacity element followed by zeros
+    pub fn initial_state<F: PrimeField, const T: usize>(&self) -> [F; T] {
+        let mut state = [F::ZERO; T];
+        state[0] = self.capacity();
+        state
+    }
+}
 
 // adapted from: https://github.com/privacy-scaling-explorations/snark-verifier
 
@@ -19,43 +71,66 @@ impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> St
         Self { inner }
     }
 
-    fn sbox_full(&mut self, constants: &[F; T]) {
-        let pow5 = |v: &F| v.square() * v.square() * v;
+    fn sbox_full(&mut self, sbox: SBox, constants: &[F; T]) {
         for (state, constant) in self.inner.iter_mut().zip(constants.iter()) {
-            *state = pow5(state) + *constant;
+            *state = sbox.apply(*state) + *constant;
         }
     }
 
-    fn sbox_part(&mut self, constant: &F) {
-        let pow5 = |v: &F| v.square() * v.square() * v;
-        self.inner[0] = pow5(&self.inner[0]) + *constant;
+    fn sbox_part(&mut self, sbox: SBox, constant: &F) {
+        self.inner[0] = sbox.apply(self.inner[0]) + *constant;
     }
 
-    fn pre_round(&mut self, inputs: &[F], pre_constants: &[F; T]) {
-        assert!(RATE == T - 1);
-        assert!(inputs.len() <= RATE);
+    // `inputs` are added to `inner[1..=rate]`, a partial block being padded with a single one
+    fn pre_round(&mut self, inputs: &[F], rate: usize, pre_constants: &[F; T]) {
+        assert!(rate < T);
+        assert!(inputs.len() <= rate);
 
-        self.inner[0] += pre_constants[0];
         self.inner
             .iter_mut()
             .zip(pre_constants.iter())
-            .skip(1)
-            .zip(inputs)
-            .for_each(|((state, constant), input)| {
-                *state = *state + *input + *constant;
-            });
+            .for_each(|(state, constant)| *state += *constant);
         self.inner
             .iter_mut()
-            .zip(pre_constants.iter())
-            .skip(1 + inputs.len())
-            .enumerate()
-            .for_each(|(idx, (state, constant))| {
-                *state = if idx == 0 {
-                    *state + F::ONE + *constant
-                } else {
-                    *state + *constant
-                };
-            });
+            .skip(1)
+            .zip(inputs)
+            .for_each(|(state, input)| *state += *input);
+        if inputs.len() < rate {
+            self.inner[1 + inputs.len()] += F::ONE;
+        }
+    }
+
+    fn permutation(&mut self, spec: &Spec<F, T, RATE>, sbox: SBox, inputs: &[F], rate: usize) {
+        let r_f = spec.r_f() / 2;
+        let mds = spec.mds_matrices().mds().rows();
+        let pre_sparse_mds = spec.mds_matrices().pre_sparse_mds().rows();
+        let sparse_matrices = spec.mds_matrices().sparse_matrices();
+
+        // First half of the full rounds
+        let constants = spec.constants().start();
+        self.pre_round(inputs, rate, &constants[0]);
+        for constants in constants.iter().skip(1).take(r_f - 1) {
+            self.sbox_full(sbox, constants);
+            self.apply_mds(&mds);
+        }
+        self.sbox_full(sbox, constants.last().unwrap());
+        self.apply_mds(&pre_sparse_mds);
+
+        // Partial rounds
+        let constants = spec.constants().partial();
+        for (constant, sparse_mds) in constants.iter().zip(sparse_matrices.iter()) {
+            self.sbox_part(sbox, constant);
+            self.apply_sparse_mds(sparse_mds);
+        }
+
+        // Second half of the full rounds
+        let constants = spec.constants().end();
+        for constants in constants.iter() {
+            self.sbox_full(sbox, constants);
+            self.apply_mds(&mds);
+        }
+        self.sbox_full(sbox, &[F::ZERO; T]);
+        self.apply_mds(&mds);
     }
 
     fn apply_mds(&mut self, mds: &[[F; T]; T]) {
@@ -92,12 +167,92 @@ impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize> St
     }
 }
 
-impl<F, const T: usize, const RATE: usize> ROConstantsTrait for Spec<F, T, RATE>
+/// The [`Spec`] of the permutation and its S-box, `x^5` unless set by [`Self::with_sbox`],
+/// taken together by [`PoseidonHash`], the transcripts of [`crate::transcript`] and
+/// [`crate::poseidon_circuit::PoseidonChip`], which checks it against the S-box of its gate.
+///
+/// The S-box has to be a permutation of `F` and the rounds enough for it, see
+/// [`SBox::rounds_are_secure`], which is checked whenever a spec is built but by
+/// [`Self::new_unchecked`].
+#[derive(Clone, Debug)]
+pub struct PoseidonSpec<F: PrimeField, const T: usize, const RATE: usize> {
+    pub(crate) spec: Spec<F, T, RATE>,
+    pub(crate) sbox: SBox,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>, const T: usize, const RATE: usize>
+    PoseidonSpec<F, T, RATE>
+{
+    /// [`Spec::new`] with the `x^5` S-box, it panics unless it is a permutation of `F` and
+    /// the rounds are enough for it
+    pub fn new(r_f: usize, r_p: usize) -> Self {
+        let spec = Self::new_unchecked(r_f, r_p);
+        spec.validate();
+        spec
+    }
+
+    /// [`Self::new`] without any check, only meant for tests whose toy rounds keep circuits
+    /// small but give no security
+    pub fn new_unchecked(r_f: usize, r_p: usize) -> Self {
+        Self {
+            spec: Spec::new(r_f, r_p),
+            sbox: SBox::default(),
+        }
+    }
+}
+
+/// Checks the spec as [`PoseidonSpec::new`] does, it used to be the constants of
+/// [`crate::ro_types::ROTrait`] and of the hashers and chips that now take a [`PoseidonSpec`]
+impl<F: PrimeField, const T: usize, const RATE: usize> From<Spec<F, T, RATE>>
+    for PoseidonSpec<F, T, RATE>
+{
+    fn from(spec: Spec<F, T, RATE>) -> Self {
+        let spec = Self {
+            spec,
+            sbox: SBox::default(),
+        };
+        spec.validate();
+        spec
+    }
+}
+
+impl<F: PrimeField, const T: usize, const RATE: usize> PoseidonSpec<F, T, RATE> {
+    /// Sets the S-box, it panics unless it is a permutation of `F` and the rounds are enough
+    /// for it, see [`SBox::rounds_are_secure`]. The constants do not depend on it.
+    pub fn with_sbox(mut self, sbox: SBox) -> Self {
+        self.sbox = sbox;
+        self.validate();
+        self
+    }
+
+    fn validate(&self) {
+        self.sbox.validate::<F>();
+        self.sbox.validate_rounds::<F>(T, self.r_f(), self.r_p());
+    }
+
+    pub fn spec(&self) -> &Spec<F, T, RATE> {
+        &self.spec
+    }
+
+    pub fn sbox(&self) -> SBox {
+        self.sbox
+    }
+
+    pub fn r_f(&self) -> usize {
+        self.spec.r_f()
+    }
+
+    pub fn r_p(&self) -> usize {
+        self.spec.constants().partial().len()
+    }
+}
+
+impl<F, const T: usize, const RATE: usize> ROConstantsTrait for PoseidonSpec<F, T, RATE>
 where
     F: PrimeField + FromUniformBytes<64>,
 {
     fn new(r_f: usize, r_p: usize) -> Self {
-        Spec::new(r_f, r_p)
+        PoseidonSpec::new(r_f, r_p)
     }
 }
 
@@ -106,19 +261,55 @@ where
     C: CurveAffine<ScalarExt = F>,
     F: PrimeField + FromUniformBytes<64>,
 {
-    type Constants = Spec<F, T, RATE>;
+    type Constants = PoseidonSpec<F, T, RATE>;
     fn new(constants: Self::Constants) -> Self {
         Self {
             spec: constants,
-            state: State::new(poseidon::State::default().words()),
+            state: State::new(Domain::default().initial_state()),
             buf: Vec::new(),
+            rate: RATE,
+            domain: Domain::default(),
             _marker: PhantomData,
         }
     }
 
+    fn absorb_scalar(&mut self, scalar: F) -> &mut Self {
+        self.update(&[scalar]);
+        self
+    }
+
+    fn absorb_scalars(&mut self, scalars: &[F]) -> &mut Self {
+        self.update(scalars);
+        self
+    }
+
+    fn absorb_native_point<P: CurveAffine<Base = C::Scalar>>(&mut self, point: &P) -> &mut Self {
+        let (x, y) = point_coordinates(point);
+        self.update(&[x, y]);
+        self
+    }
+
+    fn absorb_base(&mut self, base: &C::Base) -> &mut Self {
+        self.update(&base_to_limbs(base));
+        self
+    }
+
+    fn absorb_point(&mut self, point: &C) -> &mut Self {
+        let (x, y) = point_coordinates(point);
+        self.absorb_base(&x).absorb_base(&y)
+    }
+
     fn squeeze(&mut self) -> C::Scalar {
         self.output()
     }
+
+    fn squeeze_n(&mut self, n: usize) -> Vec<C::Scalar> {
+        self.output_n(n)
+    }
+
+    fn squeeze_n_bits(&mut self, num_bits: usize) -> C::Scalar {
+        self.output_n_bits(num_bits)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -128,9 +319,12 @@ pub struct PoseidonHash<
     const T: usize,
     const RATE: usize,
 > {
-    spec: Spec<F, T, RATE>,
+    spec: PoseidonSpec<F, T, RATE>,
     state: State<F, T, RATE>,
     buf: Vec<F>,
+    // elements absorbed and squeezed per permutation, `RATE` unless set by `with_rate`
+    rate: usize,
+    domain: Domain,
     _marker: PhantomData<C>,
 }
 
@@ -141,55 +335,392 @@ impl<
         const RATE: usize,
     > PoseidonHash<C, F, T, RATE>
 {
-    fn update(&mut self, elements: &[F]) {
+    /// Sets the rate of the sponge, the remaining `T - rate` elements of the state being
+    /// its capacity.
+    ///
+    /// `RATE` is the one of [`Spec`], whose optimized matrices need `RATE = T - 1`, any
+    /// smaller rate is thus chosen here.
+    pub fn with_rate(mut self, rate: usize) -> Self {
+        assert!(rate > 0 && rate < T);
+        self.rate = rate;
+        self
+    }
+
+    /// Sets the domain of the sponge, [`Domain::default`] otherwise. It initializes the state,
+    /// hence has to be called before anything is absorbed or squeezed.
+    pub fn with_domain(mut self, domain: Domain) -> Self {
+        assert!(
+            self.is_fresh(),
+            "nothing can be absorbed or squeezed before the domain is set"
+        );
+        self.state = State::new(domain.initial_state());
+        self.domain = domain;
+        self
+    }
+
+    // nothing has been absorbed nor squeezed yet
+    fn is_fresh(&self) -> bool {
+        self.buf.is_empty() && self.state.inner == self.domain.initial_state::<F, T>()
+    }
+
+    /// Buffers elements to be absorbed by the next [`Self::output`]
+    pub fn update(&mut self, elements: &[F]) {
         self.buf.extend_from_slice(elements);
     }
 
-    fn output(&mut self) -> F {
+    /// Hashes exactly `L` elements in [`Domain::ConstantLength`], whose capacity already
+    /// encodes `L`: the last block is completed with zeros rather than padded, and no padding
+    /// block follows a full one. The sponge has to be fresh and in the default domain.
+    pub fn hash<const L: usize>(&mut self, inputs: [F; L]) -> F {
+        assert!(
+            self.is_fresh() && self.domain == Domain::default(),
+            "hash needs a fresh sponge, its domain is set from L"
+        );
+        let domain = Domain::ConstantLength {
+            len: L,
+            output_len: 1,
+        };
+        self.state = State::new(domain.initial_state());
+        self.domain = domain;
+
+        let mut blocks = inputs.to_vec();
+        blocks.resize(L.max(1).div_ceil(self.rate) * self.rate, F::ZERO);
+        for block in blocks.chunks(self.rate) {
+            self.permutation(block);
+        }
+        self.state.inner[1]
+    }
+
+    /// Absorbs the buffered elements with padding and returns the first element of the rate
+    pub fn output(&mut self) -> F {
+        self.output_n(1)[0]
+    }
+
+    /// Absorbs the buffered elements with padding and squeezes `n` elements: the rate of the
+    /// padded permutation is read first and, while more are needed, the sponge is permuted
+    /// again over an empty padded block. Elements of the rate left unread are discarded, so
+    /// the sponge can keep absorbing after any squeeze.
+    pub fn output_n(&mut self, n: usize) -> Vec<F> {
         let buf = mem::take(&mut self.buf);
-        let exact = buf.len() % RATE == 0;
+        let exact = buf.len() % self.rate == 0;
 
-        for chunk in buf.chunks(RATE) {
+        for chunk in buf.chunks(self.rate) {
             self.permutation(chunk);
         }
         if exact {
             self.permutation(&[]);
         }
 
-        self.state.inner[1]
+        let mut output = Vec::with_capacity(n);
+        loop {
+            output.extend(
+                self.state
+                    .inner
+                    .iter()
+                    .skip(1)
+                    .take(self.rate.min(n - output.len())),
+            );
+            if output.len() == n {
+                return output;
+            }
+            self.permutation(&[]);
+        }
+    }
+
+    /// Squeezes an element and keeps its `num_bits` least significant bits
+    pub fn output_n_bits(&mut self, num_bits: usize) -> F {
+        assert!(num_bits <= F::NUM_BITS as usize);
+        let repr = self.output().to_repr();
+        repr.as_ref()
+            .iter()
+            .flat_map(|byte| (0..8).map(move |i| (*byte >> i) & 1 == 1))
+            .take(num_bits)
+            .rev()
+            .fold(F::ZERO, |acc, bit| {
+                if bit {
+                    acc.double() + F::ONE
+                } else {
+                    acc.double()
+                }
+            })
+    }
+
+    /// Compresses `RATE` elements into one with a single permutation of
+    /// `[capacity, inputs[0], .., inputs[RATE - 1]]`, without padding. The capacity is the
+    /// one of the sponge domain, [`Domain::MerkleTree`] being meant for Merkle trees.
+    ///
+    /// The sponge itself is left untouched.
+    pub fn compress(&self, inputs: [F; RATE]) -> F {
+        assert_eq!(self.rate, RATE, "compression absorbs the whole rate");
+        let mut state = State::new(self.domain.initial_state());
+        state.permutation(&self.spec.spec, self.spec.sbox, &inputs, RATE);
+        state.inner[1]
     }
 
     fn permutation(&mut self, inputs: &[F]) {
-        let r_f = self.spec.r_f() / 2;
-        let mds = self.spec.mds_matrices().mds().rows();
-        let pre_sparse_mds = self.spec.mds_matrices().pre_sparse_mds().rows();
-        let sparse_matrices = self.spec.mds_matrices().sparse_matrices();
+        self.state
+            .permutation(&self.spec.spec, self.spec.sbox, inputs, self.rate);
+    }
+}
 
-        // First half of the full rounds
-        let constants = self.spec.constants().start();
-        self.state.pre_round(inputs, &constants[0]);
-        for constants in constants.iter().skip(1).take(r_f - 1) {
-            self.state.sbox_full(constants);
-            self.state.apply_mds(&mds);
+// serde of field elements as the base64 encoding of their representation
+mod field_serde {
+    use base64::{engine::general_purpose::STANDARD as BS64, Engine};
+    use halo2curves::group::ff::PrimeField;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn encode<F: PrimeField>(value: &F) -> String {
+        BS64.encode(value.to_repr())
+    }
+
+    pub fn decode<F: PrimeField, E: de::Error>(encoded: &str) -> Result<F, E> {
+        let bytes = BS64.decode(encoded).map_err(E::custom)?;
+        let mut repr = F::Repr::default();
+        if bytes.len() != repr.as_ref().len() {
+            return Err(E::invalid_length(
+                bytes.len(),
+                &"a field element representation",
+            ));
         }
-        self.state.sbox_full(constants.last().unwrap());
-        self.state.apply_mds(&pre_sparse_mds);
+        repr.as_mut().copy_from_slice(&bytes);
+        Option::from(F::from_repr(repr)).ok_or_else(|| E::custom("non canonical field element"))
+    }
 
-        // Partial rounds
-        let constants = self.spec.constants().partial();
-        for (constant, sparse_mds) in constants.iter().zip(sparse_matrices.iter()) {
-            self.state.sbox_part(constant);
-            self.state.apply_sparse_mds(sparse_mds);
+    pub fn serialize<F: PrimeField, S: Serializer>(
+        values: &[F],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(encode))
+    }
+
+    pub fn deserialize<'de, F: PrimeField, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<F>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|encoded| decode(encoded))
+            .collect()
+    }
+}
+
+/// Authentication path of a leaf of a [`MerkleTree`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct MerklePath<F: PrimeField> {
+    pub leaf_index: usize,
+    /// Siblings from the leaf level up to the children of the root
+    #[serde(with = "field_serde")]
+    pub siblings: Vec<F>,
+}
+
+impl<F: PrimeField> MerklePath<F> {
+    /// Direction bits from the leaf level up, `true` when the node is a right child
+    pub fn directions(&self) -> Vec<bool> {
+        (0..self.siblings.len())
+            .map(|level| (self.leaf_index >> level) & 1 == 1)
+            .collect()
+    }
+
+    /// The root of the tree containing `leaf` at this path, `hasher` being
+    /// [`MerkleTree::hasher`]
+    pub fn root<C: CurveAffine<ScalarExt = F>>(
+        &self,
+        hasher: &PoseidonHash<C, F, 3, 2>,
+        leaf: F,
+    ) -> F
+    where
+        F: FromUniformBytes<64>,
+    {
+        self.siblings
+            .iter()
+            .zip(self.directions())
+            .fold(leaf, |node, (sibling, is_right)| {
+                if is_right {
+                    hasher.compress([*sibling, node])
+                } else {
+                    hasher.compress([node, *sibling])
+                }
+            })
+    }
+
+    /// Checks that `leaf` is in the tree of root `root` at this path
+    pub fn verify<C: CurveAffine<ScalarExt = F>>(
+        &self,
+        hasher: &PoseidonHash<C, F, 3, 2>,
+        root: F,
+        leaf: F,
+    ) -> bool
+    where
+        F: FromUniformBytes<64>,
+    {
+        self.leaf_index
+            .checked_shr(self.siblings.len() as u32)
+            .unwrap_or(0)
+            == 0
+            && self.root(hasher, leaf) == root
+    }
+}
+
+/// Binary Merkle tree whose nodes are [`PoseidonHash::compress`] of their children in
+/// [`Domain::MerkleTree`], see [`crate::merkle_circuit::MerkleChip`] for the in-circuit path
+/// verification
+///
+/// It is serialized with the `r_f` and `r_p` parameters and the S-box of its [`PoseidonSpec`]
+/// and all its nodes, the nodes being recomputed from the leaves and checked against the
+/// serialized ones when it is deserialized.
+#[derive(Clone, Debug)]
+pub struct MerkleTree<C: CurveAffine<ScalarExt = F>, F: PrimeField + FromUniformBytes<64>> {
+    hasher: PoseidonHash<C, F, 3, 2>,
+    // leaves first, the root last
+    layers: Vec<Vec<F>>,
+}
+
+impl<C: CurveAffine<ScalarExt = F>, F: PrimeField + FromUniformBytes<64>> MerkleTree<C, F> {
+    /// Builds the tree of `leaves`, whose number must be a power of two
+    pub fn new(spec: PoseidonSpec<F, 3, 2>, leaves: Vec<F>) -> Self {
+        assert!(leaves.len().is_power_of_two());
+        let hasher = Self::hasher(spec);
+        let mut layers = vec![leaves];
+        while layers.last().unwrap().len() > 1 {
+            let layer = layers
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|children| hasher.compress([children[0], children[1]]))
+                .collect();
+            layers.push(layer);
         }
+        Self { hasher, layers }
+    }
 
-        // Second half of the full rounds
-        let constants = self.spec.constants().end();
-        for constants in constants.iter() {
-            self.state.sbox_full(constants);
-            self.state.apply_mds(&mds);
+    /// The hasher of the nodes, the same as [`crate::merkle_circuit::MerkleChip`] with `spec`
+    pub fn hasher(spec: PoseidonSpec<F, 3, 2>) -> PoseidonHash<C, F, 3, 2> {
+        PoseidonHash::new(spec).with_domain(Domain::MerkleTree { arity: 2 })
+    }
+
+    pub fn root(&self) -> F {
+        self.layers.last().unwrap()[0]
+    }
+
+    /// Number of levels above the leaves
+    pub fn depth(&self) -> usize {
+        self.layers.len() - 1
+    }
+
+    pub fn leaves(&self) -> &[F] {
+        &self.layers[0]
+    }
+
+    /// Replaces the leaf at `leaf_index` and recomputes the nodes up to the root
+    pub fn update(&mut self, leaf_index: usize, leaf: F) {
+        assert!(leaf_index < self.leaves().len());
+        self.layers[0][leaf_index] = leaf;
+        let mut index = leaf_index;
+        for level in 0..self.depth() {
+            let left = self.layers[level][index & !1];
+            let right = self.layers[level][index | 1];
+            index >>= 1;
+            self.layers[level + 1][index] = self.hasher.compress([left, right]);
+        }
+    }
+
+    pub fn path(&self, leaf_index: usize) -> MerklePath<F> {
+        assert!(leaf_index < self.leaves().len());
+        let siblings = self
+            .layers
+            .iter()
+            .take(self.depth())
+            .enumerate()
+            .map(|(level, layer)| layer[(leaf_index >> level) ^ 1])
+            .collect();
+        MerklePath {
+            leaf_index,
+            siblings,
+        }
+    }
+
+    /// Checks that `leaf` is in this tree at `path`
+    pub fn verify(&self, leaf: F, path: &MerklePath<F>) -> bool {
+        path.verify(&self.hasher, self.root(), leaf)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MerkleTreeRepr {
+    r_f: usize,
+    r_p: usize,
+    #[serde(default)]
+    sbox: SBox,
+    layers: Vec<Vec<String>>,
+}
+
+impl<C: CurveAffine<ScalarExt = F>, F: PrimeField + FromUniformBytes<64>> Serialize
+    for MerkleTree<C, F>
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MerkleTreeRepr {
+            r_f: self.hasher.spec.r_f(),
+            r_p: self.hasher.spec.r_p(),
+            sbox: self.hasher.spec.sbox(),
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| layer.iter().map(field_serde::encode).collect())
+                .collect(),
         }
-        self.state.sbox_full(&[F::ZERO; T]);
-        self.state.apply_mds(&mds);
+        .serialize(serializer)
+    }
+}
+
+impl<'de, C: CurveAffine<ScalarExt = F>, F: PrimeField + FromUniformBytes<64>> Deserialize<'de>
+    for MerkleTree<C, F>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = MerkleTreeRepr::deserialize(deserializer)?;
+        // the Grain LFSR generating the constants encodes both on 10 bits
+        if repr.r_f == 0 || repr.r_f % 2 != 0 || repr.r_f >= 1 << 10 {
+            return Err(de::Error::custom(format!(
+                "invalid number of full rounds {}",
+                repr.r_f
+            )));
+        }
+        if repr.r_p == 0 || repr.r_p >= 1 << 10 {
+            return Err(de::Error::custom(format!(
+                "invalid number of partial rounds {}",
+                repr.r_p
+            )));
+        }
+        if !repr.sbox.is_valid::<F>() || !repr.sbox.rounds_are_secure::<F>(3, repr.r_f, repr.r_p) {
+            return Err(de::Error::custom(format!(
+                "invalid S-box x^{} for {} full and {} partial rounds",
+                repr.sbox.alpha(),
+                repr.r_f,
+                repr.r_p
+            )));
+        }
+        let spec = PoseidonSpec::new_unchecked(repr.r_f, repr.r_p).with_sbox(repr.sbox);
+        let layers = repr
+            .layers
+            .iter()
+            .map(|layer| {
+                layer
+                    .iter()
+                    .map(|encoded| field_serde::decode(encoded))
+                    .collect::<Result<Vec<F>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let leaves = match layers.first() {
+            Some(leaves) if leaves.len().is_power_of_two() => leaves.clone(),
+            _ => return Err(de::Error::custom("malformed Merkle tree layers")),
+        };
+        // the nodes are recomputed from the leaves and have to match the serialized ones
+        let tree = Self::new(spec, leaves);
+        if tree.layers != layers {
+            return Err(de::Error::custom(
+                "the Merkle tree nodes do not match its leaves",
+            ));
+        }
+        Ok(tree)
     }
 }
 
@@ -197,7 +728,8 @@ impl<
 mod tests {
     use halo2curves::{
         bn256::{Fr, G1Affine},
-        pasta::{EqAffine, Fp},
+        group::prime::PrimeCurveAffine,
+        pasta::{EpAffine, EqAffine, Fp, Fq},
     };
 
     use super::*;
@@ -209,8 +741,8 @@ mod tests {
         const R_F: usize = 8;
         const R_P: usize = 56;
         type PH = PoseidonHash<G1Affine, Fr, T, RATE>;
-        let spec = Spec::<Fr, T, RATE>::new(R_F, R_P);
-        let mut poseidon = PH::new(spec);
+        let spec = PoseidonSpec::<Fr, T, RATE>::new(R_F, R_P);
+        let mut poseidon = PH::new(spec.clone());
         for i in 0..5 {
             poseidon.update(&[Fr::from(i as u64)]);
         }
@@ -221,5 +753,358 @@ mod tests {
         )
         .unwrap();
         assert_eq!(output, out_hash);
+
+        // the constant-length hash of the same elements, without padding
+        let mut poseidon = PH::new(spec);
+        let output = poseidon.hash(std::array::from_fn::<_, 5, _>(|i| Fr::from(i as u64)));
+        // 0x05decbaadfa64b4cf80f57b3efd921844450cf83eb29f0f8195af89720251a77
+        let out_hash = Fr::from_str_vartime(
+            "2655209952549515647804273027259985186009043725127833411401695724144131644023",
+        )
+        .unwrap();
+        assert_eq!(output, out_hash);
+    }
+
+    #[test]
+    fn test_absorb() {
+        const T: usize = 3;
+        const RATE: usize = 2;
+        const R_F: usize = 8;
+        const R_P: usize = 57;
+        type PH = PoseidonHash<EqAffine, Fp, T, RATE>;
+        let spec = PoseidonSpec::<Fp, T, RATE>::new(R_F, R_P);
+
+        let generator = EpAffine::generator();
+        let coordinates = generator.coordinates().unwrap();
+        let mut expected = PH::new(spec.clone());
+        expected.update(&[
+            Fp::from(1u64),
+            *coordinates.x(),
+            *coordinates.y(),
+            Fp::from(2u64),
+            Fp::from(3u64),
+            Fp::from(0u64),
+            Fp::from(0u64),
+        ]);
+
+        let mut poseidon = PH::new(spec);
+        poseidon
+            .absorb_scalar(Fp::from(1u64))
+            .absorb_native_point(&generator)
+            .absorb_scalars(&[Fp::from(2u64), Fp::from(3u64)])
+            .absorb_native_point(&EpAffine::identity());
+        assert_eq!(poseidon.squeeze(), expected.squeeze());
+    }
+
+    #[test]
+    fn test_absorb_base() {
+        const T: usize = 3;
+        const RATE: usize = 2;
+        const R_F: usize = 8;
+        const R_P: usize = 57;
+        type PH = PoseidonHash<EqAffine, Fp, T, RATE>;
+        let spec = PoseidonSpec::<Fp, T, RATE>::new(R_F, R_P);
+
+        // 2^64 + 3
+        let base = Fq::from_u128((1 << 64) + 3);
+        let limbs = [3u64, 1, 0, 0].map(Fp::from);
+        assert_eq!(base_to_limbs::<Fq, Fp>(&base), limbs);
+
+        let mut expected = PH::new(spec.clone());
+        expected.update(&limbs);
+        let mut poseidon = PH::new(spec);
+        poseidon.absorb_base(&base);
+        assert_eq!(poseidon.squeeze(), expected.squeeze());
+    }
+
+    #[test]
+    fn test_domain() {
+        const T: usize = 3;
+        const RATE: usize = 2;
+        const R_F: usize = 8;
+        const R_P: usize = 57;
+        type PH = PoseidonHash<EqAffine, Fp, T, RATE>;
+        let spec = PoseidonSpec::<Fp, T, RATE>::new(R_F, R_P);
+
+        assert_eq!(
+            Domain::default().initial_state::<Fp, T>(),
+            poseidon::State::<Fp, T>::default().words()
+        );
+        assert_eq!(
+            Domain::ConstantLength {
+                len: 2,
+                output_len: 3
+            }
+            .capacity::<Fp>(),
+            Fp::from_u128((2 << 64) + 2)
+        );
+        assert_eq!(
+            Domain::MerkleTree { arity: 2 }.capacity::<Fp>(),
+            Fp::from(3u64)
+        );
+
+        let inputs = [Fp::from(1u64), Fp::from(2u64)];
+        let hash = |mut poseidon: PH| {
+            poseidon.update(&inputs);
+            poseidon.output()
+        };
+        assert_eq!(
+            hash(PH::new(spec.clone()).with_domain(Domain::default())),
+            hash(PH::new(spec.clone()))
+        );
+        let outputs = [
+            Domain::VariableLength { output_len: 2 },
+            Domain::ConstantLength {
+                len: 2,
+                output_len: 1,
+            },
+            Domain::MerkleTree { arity: 2 },
+            Domain::Custom { identifier: 1 },
+        ]
+        .map(|domain| hash(PH::new(spec.clone()).with_domain(domain)));
+        for (i, output) in outputs.iter().enumerate() {
+            assert_ne!(*output, hash(PH::new(spec.clone())));
+            for other in outputs.iter().skip(i + 1) {
+                assert_ne!(output, other);
+            }
+        }
+
+        // `hash` sets the constant-length domain and completes the last block with zeros
+        let unpadded = |len: usize, block: [Fp; RATE]| {
+            let domain = Domain::ConstantLength { len, output_len: 1 };
+            let mut state = State::<Fp, T, RATE>::new(domain.initial_state());
+            state.permutation(spec.spec(), spec.sbox(), &block, RATE);
+            state.inner[1]
+        };
+        assert_eq!(PH::new(spec.clone()).hash(inputs), unpadded(2, inputs));
+        assert_eq!(
+            PH::new(spec.clone()).hash([inputs[0]]),
+            unpadded(1, [inputs[0], Fp::from(0u64)])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "nothing can be absorbed or squeezed before the domain is set")]
+    fn test_domain_after_squeeze() {
+        let spec = PoseidonSpec::<Fp, 3, 2>::new(8, 57);
+        let mut poseidon = PoseidonHash::<EqAffine, Fp, 3, 2>::new(spec);
+        poseidon.output();
+        poseidon.with_domain(Domain::MerkleTree { arity: 2 });
+    }
+
+    #[test]
+    fn test_compress() {
+        const T: usize = 3;
+        const RATE: usize = 2;
+        const R_F: usize = 8;
+        const R_P: usize = 57;
+        type PH = PoseidonHash<EqAffine, Fp, T, RATE>;
+        let spec = PoseidonSpec::<Fp, T, RATE>::new(R_F, R_P);
+
+        let (left, right) = (Fp::from(1u64), Fp::from(2u64));
+        let mut poseidon = PH::new(spec.clone()).with_domain(Domain::MerkleTree { arity: 2 });
+        let node = poseidon.compress([left, right]);
+        assert_ne!(node, poseidon.compress([right, left]));
+        assert_ne!(node, PH::new(spec.clone()).compress([left, right]));
+
+        // a single permutation without padding, unlike the sponge
+        let mut state = State::<Fp, T, RATE>::new([Fp::from(3u64), left, right]);
+        state.permutation(spec.spec(), spec.sbox(), &[], 0);
+        assert_eq!(node, state.inner[1]);
+        poseidon.update(&[left, right]);
+        assert_ne!(node, poseidon.output());
+    }
+
+    #[test]
+    #[should_panic(expected = "compression absorbs the whole rate")]
+    fn test_compress_rate() {
+        let spec = PoseidonSpec::<Fp, 3, 2>::new(8, 57);
+        PoseidonHash::<EqAffine, Fp, 3, 2>::new(spec)
+            .with_rate(1)
+            .compress([Fp::from(1u64), Fp::from(2u64)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "x^3 is not a permutation of the field")]
+    fn test_invalid_sbox() {
+        PoseidonSpec::<Fr, 3, 2>::new(8, 57).with_sbox(SBox::Pow3);
+    }
+
+    #[test]
+    #[should_panic(expected = "4 full and 3 partial rounds are not enough for x^7")]
+    fn test_sbox_rounds() {
+        PoseidonSpec::<Fp, 3, 2>::new_unchecked(4, 3).with_sbox(SBox::Pow7);
+    }
+
+    #[test]
+    #[should_panic(expected = "8 full and 40 partial rounds are not enough for x^5")]
+    fn test_spec_rounds() {
+        PoseidonSpec::<Fp, 3, 2>::new(8, 40);
+    }
+
+    #[test]
+    #[should_panic(expected = "8 full and 40 partial rounds are not enough for x^5")]
+    fn test_spec_from_rounds() {
+        PoseidonSpec::<Fp, 3, 2>::from(Spec::new(8, 40));
+    }
+
+    #[test]
+    fn test_merkle_tree() {
+        const R_F: usize = 8;
+        const R_P: usize = 57;
+        let spec = PoseidonSpec::<Fp, 3, 2>::new(R_F, R_P);
+        let leaves = (0..8).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let tree = MerkleTree::<EqAffine, Fp>::new(spec.clone(), leaves.clone());
+        assert_eq!(tree.depth(), 3);
+
+        let hasher = PoseidonHash::<EqAffine, Fp, 3, 2>::new(spec)
+            .with_domain(Domain::MerkleTree { arity: 2 });
+        let node = |l, r| hasher.compress([l, r]);
+        let root = node(
+            node(node(leaves[0], leaves[1]), node(leaves[2], leaves[3])),
+            node(node(leaves[4], leaves[5]), node(leaves[6], leaves[7])),
+        );
+        assert_eq!(tree.root(), root);
+
+        let path = tree.path(5);
+        assert_eq!(path.directions(), vec![true, false, true]);
+        assert_eq!(
+            path.siblings,
+            vec![
+                leaves[4],
+                node(leaves[6], leaves[7]),
+                node(node(leaves[0], leaves[1]), node(leaves[2], leaves[3])),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merkle_tree_update() {
+        const R_F: usize = 8;
+        const R_P: usize = 57;
+        let spec = PoseidonSpec::<Fp, 3, 2>::new(R_F, R_P);
+        let mut leaves = (0..8).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let mut tree = MerkleTree::<EqAffine, Fp>::new(spec.clone(), leaves.clone());
+
+        leaves[3] = Fp::from(100u64);
+        tree.update(3, leaves[3]);
+        assert_eq!(
+            tree.root(),
+            MerkleTree::<EqAffine, Fp>::new(spec.clone(), leaves.clone()).root()
+        );
+
+        let hasher = MerkleTree::<EqAffine, Fp>::hasher(spec);
+        for (leaf_index, leaf) in leaves.iter().enumerate() {
+            let path = tree.path(leaf_index);
+            assert!(tree.verify(*leaf, &path));
+            assert!(path.verify(&hasher, tree.root(), *leaf));
+            assert!(!tree.verify(*leaf + Fp::from(1u64), &path));
+        }
+        // the index selects the directions, it must fit in the path
+        let mut path = tree.path(3);
+        path.leaf_index += 8;
+        assert!(!tree.verify(leaves[3], &path));
+        path.leaf_index = 2;
+        assert!(!tree.verify(leaves[3], &path));
+    }
+
+    #[test]
+    fn test_merkle_tree_serde() {
+        const R_F: usize = 8;
+        const R_P: usize = 57;
+        let spec = PoseidonSpec::<Fp, 3, 2>::new(R_F, R_P);
+        let leaves = (0..4).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let tree = MerkleTree::<EqAffine, Fp>::new(spec, leaves.clone());
+
+        let json = serde_json::to_string(&tree).unwrap();
+        let mut deserialized: MerkleTree<EqAffine, Fp> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.root(), tree.root());
+        assert_eq!(deserialized.leaves(), tree.leaves());
+        // the parameters are restored with the nodes
+        deserialized.update(0, leaves[0]);
+        assert_eq!(deserialized.root(), tree.root());
+
+        // a tampered node, a missing layer or invalid or too few rounds are rejected
+        let valid: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let mut tampered = valid.clone();
+        tampered["layers"][1][0] = serde_json::Value::from(field_serde::encode(&Fp::from(1u64)));
+        assert!(serde_json::from_value::<MerkleTree<EqAffine, Fp>>(tampered).is_err());
+        let mut truncated = valid.clone();
+        truncated["layers"].as_array_mut().unwrap().pop();
+        assert!(serde_json::from_value::<MerkleTree<EqAffine, Fp>>(truncated).is_err());
+        for (key, value) in [
+            ("r_f", 0),
+            ("r_f", 7),
+            ("r_f", 1 << 20),
+            ("r_p", 1 << 20),
+            ("r_p", 3),
+        ] {
+            let mut invalid = valid.clone();
+            invalid[key] = serde_json::Value::from(value);
+            assert!(serde_json::from_value::<MerkleTree<EqAffine, Fp>>(invalid).is_err());
+        }
+
+        // the S-box is restored too, and has to be valid for the field and the rounds
+        let spec = PoseidonSpec::<Fp, 3, 2>::new(R_F, R_P).with_sbox(SBox::Pow7);
+        let pow7_tree = MerkleTree::<EqAffine, Fp>::new(spec, leaves.clone());
+        assert_ne!(pow7_tree.root(), tree.root());
+        let json = serde_json::to_string(&pow7_tree).unwrap();
+        let deserialized: MerkleTree<EqAffine, Fp> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.root(), pow7_tree.root());
+        let valid: serde_json::Value = serde_json::from_str(&json).unwrap();
+        for sbox in ["Pow3", "Pow17"] {
+            let mut invalid = valid.clone();
+            invalid["sbox"] = serde_json::Value::from(sbox);
+            assert!(serde_json::from_value::<MerkleTree<EqAffine, Fp>>(invalid).is_err());
+        }
+        let mut invalid = valid;
+        invalid["r_p"] = serde_json::Value::from(3);
+        assert!(serde_json::from_value::<MerkleTree<EqAffine, Fp>>(invalid).is_err());
+
+        let path = tree.path(2);
+        let json = serde_json::to_string(&path).unwrap();
+        let deserialized: MerklePath<Fp> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, path);
+        assert!(tree.verify(leaves[2], &deserialized));
+
+        assert!(serde_json::from_str::<MerklePath<Fp>>(
+            r#"{"leaf_index":0,"siblings":["not base64"]}"#
+        )
+        .is_err());
+        let mut malformed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        malformed["siblings"][0] =
+            serde_json::Value::from(field_serde::encode(&Fp::from(1u64))[..8].to_string());
+        assert!(serde_json::from_value::<MerklePath<Fp>>(malformed).is_err());
+    }
+
+    #[test]
+    fn test_rate() {
+        const T: usize = 5;
+        const RATE: usize = 4;
+        const R_F: usize = 8;
+        const R_P: usize = 60;
+        type PH = PoseidonHash<EqAffine, Fp, T, RATE>;
+        let spec = PoseidonSpec::<Fp, T, RATE>::new(R_F, R_P);
+
+        let inputs = (0..5).map(|i| Fp::from(i as u64)).collect::<Vec<_>>();
+        let hash = |mut poseidon: PH| {
+            poseidon.update(&inputs);
+            poseidon.output_n(3)
+        };
+        assert_eq!(
+            hash(PH::new(spec.clone()).with_rate(RATE)),
+            hash(PH::new(spec.clone()))
+        );
+
+        // the rate changes both the padding and the squeezed elements
+        let outputs = (1..T)
+            .map(|rate| hash(PH::new(spec.clone()).with_rate(rate)))
+            .collect::<Vec<_>>();
+        for (i, output) in outputs.iter().enumerate() {
+            assert_eq!(output.len(), 3);
+            for other in outputs.iter().skip(i + 1) {
+                assert_ne!(output, other);
+            }
+        }
     }
 }
@@ -1,6 +1,44 @@
-use halo2_proofs::{arithmetic::CurveAffine, circuit::AssignedCell, plonk::Error};
+use ff::PrimeField;
+use halo2_proofs::{arithmetic::CurveAffine, circuit::Value, plonk::Error};
 
-use crate::main_gate::RegionCtx;
+use crate::main_gate::{repr_bit, AssignedValue, MainGate, RegionCtx, WrapValue};
+
+/// Width of the limbs that an element of the base field of a curve is absorbed as, see
+/// [`ROTrait::absorb_base`]
+pub const LIMB_BITS: usize = 64;
+
+/// Number of limbs of an element of `B`
+pub fn num_limbs<B: PrimeField>() -> usize {
+    (B::NUM_BITS as usize).div_ceil(LIMB_BITS)
+}
+
+/// The little-endian [`LIMB_BITS`]-bit limbs of the canonical representation of `base`,
+/// as elements of `F`
+pub fn base_to_limbs<B: PrimeField, F: PrimeField>(base: &B) -> Vec<F> {
+    let repr = base.to_repr();
+    (0..num_limbs::<B>())
+        .map(|i| {
+            let bytes = repr
+                .as_ref()
+                .iter()
+                .skip(i * LIMB_BITS / 8)
+                .take(LIMB_BITS / 8);
+            let limb = bytes
+                .rev()
+                .fold(0u64, |limb, byte| (limb << 8) | *byte as u64);
+            F::from(limb)
+        })
+        .collect()
+}
+
+/// The affine coordinates of `point`, `(0, 0)` for the identity which is not on the curve
+/// as long as `b != 0` in `y^2 = x^3 + a * x + b`
+pub fn point_coordinates<C: CurveAffine>(point: &C) -> (C::Base, C::Base) {
+    let coordinates: Option<_> = point.coordinates().into();
+    coordinates
+        .map(|c| (*c.x(), *c.y()))
+        .unwrap_or((C::Base::ZERO, C::Base::ZERO))
+}
 
 /// A helper trait that defines the constants associated with a hash function
 pub trait ROConstantsTrait {
@@ -14,21 +52,143 @@ pub trait ROTrait<C: CurveAffine> {
     /// Initializes the hash function
     fn new(constants: Self::Constants) -> Self;
 
+    /// Absorbs a value of the scalar field
+    fn absorb_scalar(&mut self, scalar: C::Scalar) -> &mut Self;
+
+    /// Absorbs a slice of values of the scalar field
+    fn absorb_scalars(&mut self, scalars: &[C::Scalar]) -> &mut Self;
+
+    /// Absorbs the coordinates of a point whose base field is the scalar field of `C`,
+    /// the identity is encoded as `(0, 0)`
+    fn absorb_native_point<P: CurveAffine<Base = C::Scalar>>(&mut self, point: &P) -> &mut Self;
+
+    /// Absorbs a value of the base field as its limbs, see [`base_to_limbs`]
+    fn absorb_base(&mut self, base: &C::Base) -> &mut Self;
+
+    /// Absorbs a point of `C`, encoded as the limbs of its `x` coordinate followed by the
+    /// ones of its `y` coordinate, see [`point_coordinates`] for the identity
+    fn absorb_point(&mut self, point: &C) -> &mut Self;
+
     /// Returns a challenge by hashing the internal state
     fn squeeze(&mut self) -> C::Scalar;
+
+    /// Returns `n` challenges by hashing the internal state, the sponge keeps
+    /// absorbing and squeezing afterwards
+    fn squeeze_n(&mut self, n: usize) -> Vec<C::Scalar>;
+
+    /// Returns a challenge truncated to its `num_bits` least significant bits
+    fn squeeze_n_bits(&mut self, num_bits: usize) -> C::Scalar;
+}
+
+/// A point whose affine coordinates are assigned in the native field of the circuit,
+/// e.g. a point of the other curve of a cycle. The identity is encoded as `(0, 0)`
+#[derive(Clone, Debug)]
+pub struct AssignedPoint<F: PrimeField> {
+    pub x: AssignedValue<F>,
+    pub y: AssignedValue<F>,
+}
+
+/// A value of the base field of a curve assigned in its scalar field as little-endian
+/// [`LIMB_BITS`]-bit limbs, see [`AssignedBase::assign`]
+#[derive(Clone, Debug)]
+pub struct AssignedBase<F: PrimeField> {
+    pub limbs: Vec<AssignedValue<F>>,
+}
+
+impl<F: PrimeField> AssignedBase<F> {
+    /// Assigns `base`, a value of another field `B`, as its limbs, see [`base_to_limbs`]
+    ///
+    /// The `B::NUM_BITS` bits of `base` are constrained to be boolean and below the modulus of
+    /// `B`, so that the limbs are canonical, and recomposed into the limbs.
+    pub fn assign<B: PrimeField, const T: usize>(
+        main_gate: &MainGate<F, T>,
+        ctx: &mut RegionCtx<'_, F>,
+        base: Value<B>,
+    ) -> Result<Self, Error> {
+        assert!(LIMB_BITS < F::NUM_BITS as usize);
+        let bits = (0..B::NUM_BITS as usize)
+            .map(|i| {
+                let bit = base.map(|base| F::from(repr_bit(&base, i) as u64));
+                main_gate.assign_bit(ctx, bit)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        main_gate.assert_canonical::<B>(ctx, &bits)?;
+        let limbs = bits
+            .chunks(LIMB_BITS)
+            .map(|bits| main_gate.from_bits(ctx, bits))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { limbs })
+    }
+}
+
+/// A point of a curve whose affine coordinates are assigned as limbs in its scalar field, see
+/// [`AssignedBasePoint::assign`]
+#[derive(Clone, Debug)]
+pub struct AssignedBasePoint<F: PrimeField> {
+    pub x: AssignedBase<F>,
+    pub y: AssignedBase<F>,
+}
+
+impl<F: PrimeField> AssignedBasePoint<F> {
+    /// Assigns the coordinates of `point` as limbs, see [`AssignedBase::assign`] and
+    /// [`point_coordinates`] for the identity
+    ///
+    /// The point is not constrained to be on the curve.
+    pub fn assign<C: CurveAffine, const T: usize>(
+        main_gate: &MainGate<F, T>,
+        ctx: &mut RegionCtx<'_, F>,
+        point: Value<C>,
+    ) -> Result<Self, Error> {
+        let coordinates = point.map(|point| point_coordinates(&point));
+        Ok(Self {
+            x: AssignedBase::assign(main_gate, ctx, coordinates.map(|(x, _)| x))?,
+            y: AssignedBase::assign(main_gate, ctx, coordinates.map(|(_, y)| y))?,
+        })
+    }
 }
 
 /// A helper trait that defines the behavior of a hash function that we use as an RO in the circuit model
 pub trait ROCircuitTrait<C: CurveAffine> {
+    /// A type representing the configuration of the gates the hash function is laid out on
+    type Config;
+
     /// A type representing constants/parameters associated with the hash function
     type Constants: ROConstantsTrait;
 
     /// Initializes the hash function
-    fn new(constants: Self::Constants) -> Self;
+    fn new(config: Self::Config, constants: Self::Constants) -> Self;
 
-    #[allow(clippy::type_complexity)]
+    /// Absorbs a value of the scalar field
+    fn absorb_scalar(&mut self, scalar: WrapValue<C::Scalar>) -> &mut Self;
+
+    /// Absorbs the coordinates of a point whose base field is the scalar field of `C`
+    fn absorb_native_point(&mut self, point: &AssignedPoint<C::Scalar>) -> &mut Self;
+
+    /// Absorbs a value of the base field as its limbs, matching [`ROTrait::absorb_base`]
+    fn absorb_base(&mut self, base: &AssignedBase<C::Scalar>) -> &mut Self;
+
+    /// Absorbs a point of `C`, matching [`ROTrait::absorb_point`]
+    fn absorb_point(&mut self, point: &AssignedBasePoint<C::Scalar>) -> &mut Self;
+
+    /// Returns a challenge by hashing the internal state
     fn squeeze(
         &mut self,
         ctx: &mut RegionCtx<'_, C::Scalar>,
-    ) -> Result<Vec<AssignedCell<C::Scalar, C::Scalar>>, Error>;
+    ) -> Result<AssignedValue<C::Scalar>, Error>;
+
+    /// Returns `n` challenges by hashing the internal state, matching [`ROTrait::squeeze_n`]
+    fn squeeze_n(
+        &mut self,
+        ctx: &mut RegionCtx<'_, C::Scalar>,
+        n: usize,
+    ) -> Result<Vec<AssignedValue<C::Scalar>>, Error>;
+
+    /// Returns the little-endian bits of a challenge truncated to `num_bits` together
+    /// with the truncated challenge, matching [`ROTrait::squeeze_n_bits`]
+    #[allow(clippy::type_complexity)]
+    fn squeeze_n_bits(
+        &mut self,
+        ctx: &mut RegionCtx<'_, C::Scalar>,
+        num_bits: usize,
+    ) -> Result<(Vec<AssignedValue<C::Scalar>>, AssignedValue<C::Scalar>), Error>;
 }
@@ -0,0 +1,245 @@
+use std::marker::PhantomData;
+
+use ff::PrimeField;
+use halo2_proofs::{
+    circuit::{Chip, Value},
+    plonk::{Advice, Column, ConstraintSystem, Error, Fixed},
+    poly::Rotation,
+};
+
+use crate::main_gate::{AssignedValue, MainGateConfig, RegionCtx, SBox};
+
+/// A round of the optimized Poseidon permutation, the round constants are moved through
+/// the linear layer so that every round computes `s_next = M * sbox(s) + rc`
+#[derive(Clone, Debug)]
+pub enum Round<F: PrimeField, const T: usize> {
+    /// The S-box is applied to every element and `M = mds`
+    Full { mds: [[F; T]; T], rc: [F; T] },
+    /// The S-box is applied to `s[0]` only and `M` is sparse: its first row is `row`,
+    /// its first column is `col` (with `col[0] = row[0]`) and the rest is the identity
+    Partial {
+        row: [F; T],
+        col: [F; T],
+        rc: [F; T],
+    },
+}
+
+/// Configuration of [`RoundGate`], it shares the `state` columns of [`MainGate`]
+///
+/// [`MainGate`]: crate::main_gate::MainGate
+#[derive(Clone, Debug)]
+pub struct RoundGateConfig<const T: usize> {
+    pub(crate) state: [Column<Advice>; T],
+    // enables a full round between the current and the next row
+    pub(crate) q_full: Column<Fixed>,
+    // enables a partial round between the current and the next row
+    pub(crate) q_partial: Column<Fixed>,
+    // per-row matrix, partial rounds only use its first row and first column
+    pub(crate) mds: [[Column<Fixed>; T]; T],
+    pub(crate) rc: [Column<Fixed>; T],
+    // the S-box of the main gate
+    pub(crate) sbox: SBox,
+}
+
+/// A gate computing a whole round of Poseidon in one row, the next state being
+/// laid out on the `state` columns of the next row
+#[derive(Debug)]
+pub struct RoundGate<F: PrimeField, const T: usize> {
+    config: RoundGateConfig<T>,
+    _marker: PhantomData<F>,
+}
+
+impl<F: PrimeField, const T: usize> Chip<F> for RoundGate<F, T> {
+    type Config = RoundGateConfig<T>;
+    type Loaded = ();
+
+    fn config(&self) -> &Self::Config {
+        &self.config
+    }
+
+    fn loaded(&self) -> &Self::Loaded {
+        &()
+    }
+}
+
+impl<F: PrimeField, const T: usize> RoundGate<F, T> {
+    pub fn new(config: RoundGateConfig<T>) -> Self {
+        Self {
+            config,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn configure(
+        meta: &mut ConstraintSystem<F>,
+        main_gate_config: &MainGateConfig<T>,
+        fix_cols: &mut (impl Iterator<Item = Column<Fixed>> + Clone),
+    ) -> RoundGateConfig<T> {
+        let state = main_gate_config.state;
+        let q_full = fix_cols.next().unwrap();
+        let q_partial = fix_cols.next().unwrap();
+        let mds = [0; T].map(|_| [0; T].map(|_| fix_cols.next().unwrap()));
+        let rc = [0; T].map(|_| fix_cols.next().unwrap());
+        let sbox = main_gate_config.sbox;
+
+        meta.create_gate(
+            "q_full*(sum_j(mds[i][j]*sbox(s[j])) + rc[i] - s_next[i])=0",
+            |meta| {
+                let s = state
+                    .iter()
+                    .map(|s| meta.query_advice(*s, Rotation::cur()))
+                    .collect::<Vec<_>>();
+                let s_next = state
+                    .iter()
+                    .map(|s| meta.query_advice(*s, Rotation::next()))
+                    .collect::<Vec<_>>();
+                let q_full = meta.query_fixed(q_full, Rotation::cur());
+                mds.iter()
+                    .zip(rc.iter())
+                    .zip(s_next)
+                    .map(|((mds_row, rc), s_next)| {
+                        let rc = meta.query_fixed(*rc, Rotation::cur());
+                        let res = mds_row
+                            .iter()
+                            .zip(s.iter())
+                            .fold(rc - s_next, |acc, (m, s)| {
+                                acc + meta.query_fixed(*m, Rotation::cur())
+                                    * sbox.expression(s.clone())
+                            });
+                        q_full.clone() * res
+                    })
+                    .collect::<Vec<_>>()
+            },
+        );
+
+        meta.create_gate(
+            "q_partial*(mds[0][0]*sbox(s[0]) + sum_{j>0}(mds[0][j]*s[j]) + rc[0] - s_next[0])=0, \
+            q_partial*(mds[i][0]*sbox(s[0]) + s[i] + rc[i] - s_next[i])=0",
+            |meta| {
+                let s = state
+                    .iter()
+                    .map(|s| meta.query_advice(*s, Rotation::cur()))
+                    .collect::<Vec<_>>();
+                let s_next = state
+                    .iter()
+                    .map(|s| meta.query_advice(*s, Rotation::next()))
+                    .collect::<Vec<_>>();
+                let q_partial = meta.query_fixed(q_partial, Rotation::cur());
+                let s0_sbox = sbox.expression(s[0].clone());
+                let first = mds[0].iter().zip(s.iter()).skip(1).fold(
+                    meta.query_fixed(mds[0][0], Rotation::cur()) * s0_sbox.clone()
+                        + meta.query_fixed(rc[0], Rotation::cur())
+                        - s_next[0].clone(),
+                    |acc, (m, s)| acc + meta.query_fixed(*m, Rotation::cur()) * s.clone(),
+                );
+                let rest = (1..T).map(|i| {
+                    meta.query_fixed(mds[i][0], Rotation::cur()) * s0_sbox.clone()
+                        + s[i].clone()
+                        + meta.query_fixed(rc[i], Rotation::cur())
+                        - s_next[i].clone()
+                });
+                std::iter::once(first)
+                    .chain(rest)
+                    .map(|res| q_partial.clone() * res)
+                    .collect::<Vec<_>>()
+            },
+        );
+
+        RoundGateConfig {
+            state,
+            q_full,
+            q_partial,
+            mds,
+            rc,
+            sbox,
+        }
+    }
+
+    /// Lays out consecutive rounds, one row each: `state` is copied into the current row and
+    /// every round computes the state of the next row. The returned state sits on the last
+    /// row, which is left afterwards.
+    pub fn rounds(
+        &self,
+        ctx: &mut RegionCtx<'_, F>,
+        rounds: &[Round<F, T>],
+        state: &[AssignedValue<F>; T],
+    ) -> Result<[AssignedValue<F>; T], Error> {
+        let sbox = |v: Value<F>| self.config.sbox.apply_value(v);
+
+        let mut cur = Vec::with_capacity(T);
+        for (s, col) in state.iter().zip(self.config.state.iter()) {
+            let si = ctx.assign_advice(|| "rounds: state", *col, s.value().copied())?;
+            ctx.constrain_equal(si.cell(), s.cell())?;
+            cur.push(si);
+        }
+
+        for (round_idx, round) in rounds.iter().enumerate() {
+            let vals = cur.iter().map(|s| s.value().copied()).collect::<Vec<_>>();
+            let (q, mds, rc, next_vals) = match round {
+                Round::Full { mds, rc } => {
+                    let next_vals = mds
+                        .iter()
+                        .zip(rc.iter())
+                        .map(|(mds_row, rc)| {
+                            mds_row
+                                .iter()
+                                .zip(vals.iter())
+                                .fold(Value::known(*rc), |acc, (m, s)| {
+                                    acc + sbox(*s) * Value::known(*m)
+                                })
+                        })
+                        .collect::<Vec<_>>();
+                    (self.config.q_full, *mds, *rc, next_vals)
+                }
+                Round::Partial { row, col, rc } => {
+                    let s0_sbox = sbox(vals[0]);
+                    let first = row.iter().zip(vals.iter()).skip(1).fold(
+                        s0_sbox * Value::known(row[0]) + Value::known(rc[0]),
+                        |acc, (m, s)| acc + *s * Value::known(*m),
+                    );
+                    let next_vals = std::iter::once(first)
+                        .chain((1..T).map(|i| {
+                            s0_sbox * Value::known(col[i]) + vals[i] + Value::known(rc[i])
+                        }))
+                        .collect::<Vec<_>>();
+
+                    let mut mds = [[F::ZERO; T]; T];
+                    mds[0] = *row;
+                    for i in 1..T {
+                        mds[i][0] = col[i];
+                    }
+                    (self.config.q_partial, mds, *rc, next_vals)
+                }
+            };
+
+            ctx.assign_fixed(|| format!("round {}: q", round_idx), q, F::ONE)?;
+            for i in 0..T {
+                for j in 0..T {
+                    ctx.assign_fixed(
+                        || format!("round {}: mds", round_idx),
+                        self.config.mds[i][j],
+                        mds[i][j],
+                    )?;
+                }
+                ctx.assign_fixed(
+                    || format!("round {}: rc", round_idx),
+                    self.config.rc[i],
+                    rc[i],
+                )?;
+            }
+
+            ctx.next();
+            cur.clear();
+            for (val, col) in next_vals.into_iter().zip(self.config.state.iter()) {
+                cur.push(ctx.assign_advice(
+                    || format!("round {}: next state", round_idx),
+                    *col,
+                    val,
+                )?);
+            }
+        }
+        ctx.next();
+
+        Ok(cur.try_into().unwrap())
+    }
+}
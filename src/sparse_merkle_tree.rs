@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use halo2_proofs::arithmetic::CurveAffine;
+use halo2curves::group::ff::{FromUniformBytes, PrimeField};
+
+use crate::poseidon_hash::{MerkleTree, PoseidonHash, PoseidonSpec};
+
+/// Depth of a [`SparseMerkleTree`], whose keys are the field elements below `2^DEPTH`: all of
+/// them in BN254, whose modulus has 254 bits, and the ones with an unset most significant bit
+/// in the 255-bit pasta fields
+pub const DEPTH: usize = 254;
+
+/// Little-endian bits of the canonical representation of `key`, `DEPTH` of them, `None` when
+/// `key` does not fit in them
+pub fn key_bits<F: PrimeField>(key: &F) -> Option<Vec<bool>> {
+    assert!(DEPTH <= F::NUM_BITS as usize);
+    let repr = key.to_repr();
+    let bits = (0..F::NUM_BITS as usize)
+        .map(|i| (repr.as_ref()[i / 8] >> (i % 8)) & 1 == 1)
+        .collect::<Vec<_>>();
+    bits[DEPTH..]
+        .iter()
+        .all(|bit| !bit)
+        .then(|| bits[..DEPTH].to_vec())
+}
+
+/// Authentication path of a key of a [`SparseMerkleTree`], it proves membership when
+/// `value` is not zero and non-membership otherwise
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SparseMerklePath<F: PrimeField> {
+    pub key: F,
+    pub value: F,
+    /// Siblings from the leaf level up to the children of the root
+    pub siblings: Vec<F>,
+}
+
+impl<F: PrimeField + FromUniformBytes<64>> SparseMerklePath<F> {
+    pub fn is_membership(&self) -> bool {
+        self.value != F::ZERO
+    }
+
+    /// The root of the tree holding `value` at `key`, `hasher` being [`MerkleTree::hasher`],
+    /// the key has to fit in [`DEPTH`] bits
+    pub fn root<C: CurveAffine<ScalarExt = F>>(&self, hasher: &PoseidonHash<C, F, 3, 2>) -> F {
+        let bits = key_bits(&self.key).expect("the key does not fit in DEPTH bits");
+        self.siblings
+            .iter()
+            .zip(bits)
+            .fold(self.value, |node, (sibling, is_right)| {
+                if is_right {
+                    hasher.compress([*sibling, node])
+                } else {
+                    hasher.compress([node, *sibling])
+                }
+            })
+    }
+
+    pub fn verify<C: CurveAffine<ScalarExt = F>>(
+        &self,
+        hasher: &PoseidonHash<C, F, 3, 2>,
+        root: F,
+    ) -> bool {
+        self.siblings.len() == DEPTH && key_bits(&self.key).is_some() && self.root(hasher) == root
+    }
+}
+
+/// Sparse Merkle tree of depth [`DEPTH`], the leaf of a key being at the index given by its
+/// canonical bits. Empty leaves are zero and nodes are hashed as in [`MerkleTree`], see
+/// [`crate::merkle_circuit::SparseMerkleChip`] for the in-circuit verification
+#[derive(Clone, Debug)]
+pub struct SparseMerkleTree<C: CurveAffine<ScalarExt = F>, F: PrimeField + FromUniformBytes<64>> {
+    hasher: PoseidonHash<C, F, 3, 2>,
+    // `empty[level]` is the root of an empty subtree of height `level`
+    empty: Vec<F>,
+    // non-empty nodes by the bits of their index, most significant first, hence a leaf has
+    // `depth` of them and the root none
+    nodes: HashMap<Vec<bool>, F>,
+}
+
+impl<C: CurveAffine<ScalarExt = F>, F: PrimeField + FromUniformBytes<64>> SparseMerkleTree<C, F> {
+    pub fn new(spec: PoseidonSpec<F, 3, 2>) -> Self {
+        let hasher = MerkleTree::<C, F>::hasher(spec);
+        let mut empty = vec![F::ZERO];
+        for _ in 0..DEPTH {
+            let node = *empty.last().unwrap();
+            empty.push(hasher.compress([node, node]));
+        }
+        Self {
+            hasher,
+            empty,
+            nodes: HashMap::new(),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        DEPTH
+    }
+
+    pub fn root(&self) -> F {
+        self.node(&[])
+    }
+
+    /// The value at `key`, zero when absent, the key has to fit in [`DEPTH`] bits as in the
+    /// other methods
+    pub fn get(&self, key: &F) -> F {
+        let bits = key_bits(key).expect("the key does not fit in DEPTH bits");
+        self.node(&bits.into_iter().rev().collect::<Vec<_>>())
+    }
+
+    /// Inserts a non-zero `value` at an absent `key`
+    pub fn insert(&mut self, key: F, value: F) {
+        assert!(value != F::ZERO, "zero is the empty leaf");
+        assert!(self.get(&key) == F::ZERO, "the key is already present");
+        self.update(key, value);
+    }
+
+    /// Sets the value at `key`, zero removing it, and recomputes the nodes up to the root
+    pub fn update(&mut self, key: F, value: F) {
+        let bits = key_bits(&key).expect("the key does not fit in DEPTH bits");
+        let mut node = value;
+        for level in 0..self.depth() {
+            let mut index = bits[level..].iter().rev().copied().collect::<Vec<_>>();
+            self.set_node(index.clone(), node);
+            let last = index.last_mut().unwrap();
+            *last = !*last;
+            let sibling = self.node(&index);
+            node = if bits[level] {
+                self.hasher.compress([sibling, node])
+            } else {
+                self.hasher.compress([node, sibling])
+            };
+        }
+        self.set_node(Vec::new(), node);
+    }
+
+    /// The path of `key`, proving its membership or its non-membership
+    pub fn prove(&self, key: F) -> SparseMerklePath<F> {
+        let bits = key_bits(&key).expect("the key does not fit in DEPTH bits");
+        let siblings = (0..self.depth())
+            .map(|level| {
+                let mut index = bits[level..].iter().rev().copied().collect::<Vec<_>>();
+                let last = index.last_mut().unwrap();
+                *last = !*last;
+                self.node(&index)
+            })
+            .collect();
+        SparseMerklePath {
+            key,
+            value: self.get(&key),
+            siblings,
+        }
+    }
+
+    pub fn verify(&self, path: &SparseMerklePath<F>) -> bool {
+        path.verify(&self.hasher, self.root())
+    }
+
+    fn node(&self, index: &[bool]) -> F {
+        self.nodes
+            .get(index)
+            .copied()
+            .unwrap_or(self.empty[self.depth() - index.len()])
+    }
+
+    fn set_node(&mut self, index: Vec<bool>, node: F) {
+        if node == self.empty[self.depth() - index.len()] {
+            self.nodes.remove(&index);
+        } else {
+            self.nodes.insert(index, node);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::{
+        group::ff::Field,
+        pasta::{EqAffine, Fp},
+    };
+
+    use super::*;
+
+    const R_F: usize = 8;
+    const R_P: usize = 57;
+
+    #[test]
+    fn test_sparse_merkle_tree() {
+        let spec = PoseidonSpec::<Fp, 3, 2>::new(R_F, R_P);
+        let hasher = MerkleTree::<EqAffine, Fp>::hasher(spec.clone());
+        let mut tree = SparseMerkleTree::<EqAffine, Fp>::new(spec);
+        let empty_root = tree.root();
+        assert_eq!(tree.depth(), 254);
+
+        // the most significant bit of a key is bit 253
+        let key = Fp::from(2u64).pow_vartime([253]) + Fp::from(5u64);
+        let other_key = Fp::from(6u64);
+        let path = tree.prove(key);
+        assert!(!path.is_membership());
+        assert!(tree.verify(&path));
+
+        tree.insert(key, Fp::from(1u64));
+        tree.insert(other_key, Fp::from(2u64));
+        assert_ne!(tree.root(), empty_root);
+        assert_eq!(tree.get(&key), Fp::from(1u64));
+
+        let path = tree.prove(key);
+        assert!(path.is_membership());
+        assert!(path.verify(&hasher, tree.root()));
+        let absent = tree.prove(Fp::from(7u64));
+        assert!(!absent.is_membership());
+        assert!(tree.verify(&absent));
+
+        // an outdated or forged path fails
+        let mut forged = absent.clone();
+        forged.value = Fp::from(1u64);
+        assert!(!tree.verify(&forged));
+        tree.update(key, Fp::from(3u64));
+        assert!(!tree.verify(&path));
+
+        // removing every key restores the empty tree
+        tree.update(key, Fp::from(0u64));
+        tree.update(other_key, Fp::from(0u64));
+        assert_eq!(tree.root(), empty_root);
+        assert!(tree.nodes.is_empty());
+
+        // a key of 255 bits has no leaf
+        assert!(key_bits(&-Fp::from(5u64)).is_none());
+        let mut outside = path.clone();
+        outside.key = -Fp::from(5u64);
+        assert!(!tree.verify(&outside));
+    }
+
+    #[test]
+    #[should_panic(expected = "the key does not fit in DEPTH bits")]
+    fn test_sparse_merkle_tree_key_range() {
+        let mut tree = SparseMerkleTree::<EqAffine, Fp>::new(PoseidonSpec::new(R_F, R_P));
+        tree.insert(-Fp::from(5u64), Fp::from(1u64));
+    }
+}
@@ -1,13 +1,13 @@
 use ff::{FromUniformBytes, PrimeField};
 use halo2_proofs::{
-    circuit::{Layouter, SimpleFloorPlanner},
+    circuit::{Layouter, SimpleFloorPlanner, Value},
     plonk::{Circuit, Column, ConstraintSystem, Error, Instance},
 };
-use poseidon::Spec;
 
 use crate::{
-    main_gate::{MainGate, MainGateConfig, RegionCtx},
+    main_gate::{MainGate, MainGateConfig, RegionCtx, WrapValue},
     poseidon_circuit::PoseidonChip,
+    poseidon_hash::PoseidonSpec,
 };
 
 const T: usize = 4;
@@ -21,22 +21,32 @@ pub struct TestCircuitConfig {
     instance: Column<Instance>,
 }
 
-pub struct TestCircuit<F: PrimeField> {
-    inputs: Vec<F>,
+/// The largest `L` whose circuit fits in `2^10` rows, `L / RATE + 1` permutations of
+/// `T + (R_F + R_P) * T` rows being laid out
+pub const MAX_INPUT_LEN: usize = 8;
+
+/// Hashes `L` private inputs with the sponge, i.e. with padding, and exposes the output as
+/// the only public input. The layout only depends on `L`
+pub struct TestCircuit<F: PrimeField, const L: usize> {
+    inputs: [Value<F>; L],
 }
 
-impl<F: PrimeField> TestCircuit<F> {
-    pub fn new(inputs: Vec<F>) -> Self {
-        Self { inputs }
+impl<F: PrimeField, const L: usize> TestCircuit<F, L> {
+    pub fn new(inputs: [F; L]) -> Self {
+        Self {
+            inputs: inputs.map(Value::known),
+        }
     }
 }
 
-impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for TestCircuit<F> {
+impl<F: PrimeField + FromUniformBytes<64>, const L: usize> Circuit<F> for TestCircuit<F, L> {
     type Config = TestCircuitConfig;
     type FloorPlanner = SimpleFloorPlanner;
 
     fn without_witnesses(&self) -> Self {
-        Self { inputs: Vec::new() }
+        Self {
+            inputs: [Value::unknown(); L],
+        }
     }
 
     fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
@@ -53,13 +63,13 @@ impl<F: PrimeField + FromUniformBytes<64>> Circuit<F> for TestCircuit<F> {
         config: Self::Config,
         mut layouter: impl Layouter<F>,
     ) -> Result<(), Error> {
-        let spec = Spec::<F, T, RATE>::new(R_F, R_P);
-        let mut pchip = PoseidonChip::new(config.pconfig, spec);
-        pchip.update(self.inputs.clone());
+        let spec = PoseidonSpec::<F, T, RATE>::new(R_F, R_P);
         let output = layouter.assign_region(
             || "poseidon hash",
             |region| {
                 let ctx = &mut RegionCtx::new(region, 0);
+                let mut pchip = PoseidonChip::new(config.pconfig.clone(), spec.clone());
+                pchip.update_wrapped(self.inputs.map(WrapValue::from).to_vec());
                 pchip.squeeze(ctx)
             },
         )?;
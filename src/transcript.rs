@@ -0,0 +1,357 @@
+use std::io::{self, Read, Write};
+
+use halo2_proofs::{
+    arithmetic::CurveAffine,
+    transcript::{
+        Challenge255, EncodedChallenge, Transcript, TranscriptRead, TranscriptReadBuffer,
+        TranscriptWrite, TranscriptWriterBuffer,
+    },
+};
+use halo2curves::group::ff::{FromUniformBytes, PrimeField};
+
+use crate::{
+    poseidon_hash::{PoseidonHash, PoseidonSpec},
+    ro_types::{ROConstantsTrait, ROTrait},
+};
+
+// the challenge of `Challenge255` whose scalar is `scalar`: the representation is extended
+// with zeros to the 64 bytes reduced by `FromUniformBytes`, which leaves it unchanged
+fn challenge<C: CurveAffine>(scalar: C::Scalar) -> Challenge255<C>
+where
+    C::Scalar: FromUniformBytes<64>,
+{
+    let mut bytes = [0u8; 64];
+    let repr = scalar.to_repr();
+    bytes[..repr.as_ref().len()].copy_from_slice(repr.as_ref());
+    Challenge255::new(&bytes)
+}
+
+/// Transcript reading a proof and hashing it with [`PoseidonHash`], the challenges are the
+/// squeezed elements as [`Challenge255`]
+///
+/// Points are absorbed as in [`ROTrait::absorb_point`], so that the transcript can be
+/// replayed in-circuit by [`crate::transcript_circuit::TranscriptChip`].
+#[derive(Debug)]
+pub struct PoseidonRead<
+    R: Read,
+    C: CurveAffine,
+    const T: usize,
+    const RATE: usize,
+    const R_F: usize,
+    const R_P: usize,
+> where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    hasher: PoseidonHash<C, C::ScalarExt, T, RATE>,
+    reader: R,
+}
+
+impl<
+        R: Read,
+        C: CurveAffine,
+        const T: usize,
+        const RATE: usize,
+        const R_F: usize,
+        const R_P: usize,
+    > PoseidonRead<R, C, T, RATE, R_F, R_P>
+where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    /// Creates a transcript over `spec`, e.g. with another S-box than the one of `init`
+    ///
+    /// Panics if the rounds of `spec` differ from `R_F` and `R_P`, which `init` would use
+    pub fn new(reader: R, spec: PoseidonSpec<C::ScalarExt, T, RATE>) -> Self {
+        assert!(
+            spec.r_f() == R_F && spec.r_p() == R_P,
+            "the spec has {} full and {} partial rounds instead of {R_F} and {R_P}",
+            spec.r_f(),
+            spec.r_p(),
+        );
+        Self {
+            hasher: PoseidonHash::new(spec),
+            reader,
+        }
+    }
+}
+
+impl<
+        R: Read,
+        C: CurveAffine,
+        const T: usize,
+        const RATE: usize,
+        const R_F: usize,
+        const R_P: usize,
+    > Transcript<C, Challenge255<C>> for PoseidonRead<R, C, T, RATE, R_F, R_P>
+where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    fn squeeze_challenge(&mut self) -> Challenge255<C> {
+        challenge(self.hasher.squeeze())
+    }
+
+    fn common_point(&mut self, point: C) -> io::Result<()> {
+        self.hasher.absorb_point(&point);
+        Ok(())
+    }
+
+    fn common_scalar(&mut self, scalar: C::Scalar) -> io::Result<()> {
+        self.hasher.absorb_scalar(scalar);
+        Ok(())
+    }
+}
+
+impl<
+        R: Read,
+        C: CurveAffine,
+        const T: usize,
+        const RATE: usize,
+        const R_F: usize,
+        const R_P: usize,
+    > TranscriptRead<C, Challenge255<C>> for PoseidonRead<R, C, T, RATE, R_F, R_P>
+where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    fn read_point(&mut self) -> io::Result<C> {
+        let mut compressed = C::Repr::default();
+        self.reader.read_exact(compressed.as_mut())?;
+        let point: C = Option::from(C::from_bytes(&compressed)).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid point encoding in proof",
+            )
+        })?;
+        self.common_point(point)?;
+        Ok(point)
+    }
+
+    fn read_scalar(&mut self) -> io::Result<C::Scalar> {
+        let mut data = <C::Scalar as PrimeField>::Repr::default();
+        self.reader.read_exact(data.as_mut())?;
+        let scalar: C::Scalar = Option::from(C::Scalar::from_repr(data)).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid field element encoding in proof",
+            )
+        })?;
+        self.common_scalar(scalar)?;
+        Ok(scalar)
+    }
+}
+
+impl<
+        R: Read,
+        C: CurveAffine,
+        const T: usize,
+        const RATE: usize,
+        const R_F: usize,
+        const R_P: usize,
+    > TranscriptReadBuffer<R, C, Challenge255<C>> for PoseidonRead<R, C, T, RATE, R_F, R_P>
+where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    /// Initializes the transcript with the [`PoseidonSpec`] of `R_F` full and `R_P` partial
+    /// rounds, see [`Self::new`] for another S-box
+    fn init(reader: R) -> Self {
+        Self::new(reader, ROConstantsTrait::new(R_F, R_P))
+    }
+}
+
+/// Transcript writing a proof and hashing it with [`PoseidonHash`], see [`PoseidonRead`]
+#[derive(Debug)]
+pub struct PoseidonWrite<
+    W: Write,
+    C: CurveAffine,
+    const T: usize,
+    const RATE: usize,
+    const R_F: usize,
+    const R_P: usize,
+> where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    hasher: PoseidonHash<C, C::ScalarExt, T, RATE>,
+    writer: W,
+}
+
+impl<
+        W: Write,
+        C: CurveAffine,
+        const T: usize,
+        const RATE: usize,
+        const R_F: usize,
+        const R_P: usize,
+    > PoseidonWrite<W, C, T, RATE, R_F, R_P>
+where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    /// Creates a transcript over `spec`, e.g. with another S-box than the one of `init`
+    ///
+    /// Panics if the rounds of `spec` differ from `R_F` and `R_P`, which `init` would use
+    pub fn new(writer: W, spec: PoseidonSpec<C::ScalarExt, T, RATE>) -> Self {
+        assert!(
+            spec.r_f() == R_F && spec.r_p() == R_P,
+            "the spec has {} full and {} partial rounds instead of {R_F} and {R_P}",
+            spec.r_f(),
+            spec.r_p(),
+        );
+        Self {
+            hasher: PoseidonHash::new(spec),
+            writer,
+        }
+    }
+}
+
+impl<
+        W: Write,
+        C: CurveAffine,
+        const T: usize,
+        const RATE: usize,
+        const R_F: usize,
+        const R_P: usize,
+    > Transcript<C, Challenge255<C>> for PoseidonWrite<W, C, T, RATE, R_F, R_P>
+where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    fn squeeze_challenge(&mut self) -> Challenge255<C> {
+        challenge(self.hasher.squeeze())
+    }
+
+    fn common_point(&mut self, point: C) -> io::Result<()> {
+        self.hasher.absorb_point(&point);
+        Ok(())
+    }
+
+    fn common_scalar(&mut self, scalar: C::Scalar) -> io::Result<()> {
+        self.hasher.absorb_scalar(scalar);
+        Ok(())
+    }
+}
+
+impl<
+        W: Write,
+        C: CurveAffine,
+        const T: usize,
+        const RATE: usize,
+        const R_F: usize,
+        const R_P: usize,
+    > TranscriptWrite<C, Challenge255<C>> for PoseidonWrite<W, C, T, RATE, R_F, R_P>
+where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    fn write_point(&mut self, point: C) -> io::Result<()> {
+        self.common_point(point)?;
+        self.writer.write_all(point.to_bytes().as_ref())
+    }
+
+    fn write_scalar(&mut self, scalar: C::Scalar) -> io::Result<()> {
+        self.common_scalar(scalar)?;
+        self.writer.write_all(scalar.to_repr().as_ref())
+    }
+}
+
+impl<
+        W: Write,
+        C: CurveAffine,
+        const T: usize,
+        const RATE: usize,
+        const R_F: usize,
+        const R_P: usize,
+    > TranscriptWriterBuffer<W, C, Challenge255<C>> for PoseidonWrite<W, C, T, RATE, R_F, R_P>
+where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    /// Initializes the transcript with the [`PoseidonSpec`] of `R_F` full and `R_P` partial
+    /// rounds, see [`Self::new`] for another S-box
+    fn init(writer: W) -> Self {
+        Self::new(writer, ROConstantsTrait::new(R_F, R_P))
+    }
+
+    fn finalize(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2curves::{
+        group::prime::PrimeCurveAffine,
+        pasta::{EqAffine, Fp},
+    };
+
+    use super::*;
+    use crate::main_gate::SBox;
+
+    const T: usize = 3;
+    const RATE: usize = 2;
+    const R_F: usize = 8;
+    const R_P: usize = 57;
+
+    #[test]
+    fn test_poseidon_transcript() {
+        let mut transcript = PoseidonWrite::<_, EqAffine, T, RATE, R_F, R_P>::init(Vec::new());
+        transcript.common_scalar(Fp::from(1u64)).unwrap();
+        transcript.write_point(EqAffine::generator()).unwrap();
+        transcript.write_scalar(Fp::from(2u64)).unwrap();
+        let first = transcript.squeeze_challenge().get_scalar();
+        transcript.write_point(EqAffine::identity()).unwrap();
+        let second = transcript.squeeze_challenge().get_scalar();
+        let proof = transcript.finalize();
+
+        let mut hasher = PoseidonHash::<EqAffine, Fp, T, RATE>::new(PoseidonSpec::new(R_F, R_P));
+        hasher
+            .absorb_scalar(Fp::from(1u64))
+            .absorb_point(&EqAffine::generator())
+            .absorb_scalar(Fp::from(2u64));
+        assert_eq!(first, hasher.squeeze());
+        assert_eq!(second, hasher.absorb_point(&EqAffine::identity()).squeeze());
+
+        let mut transcript = PoseidonRead::<_, EqAffine, T, RATE, R_F, R_P>::init(proof.as_slice());
+        transcript.common_scalar(Fp::from(1u64)).unwrap();
+        assert_eq!(transcript.read_point().unwrap(), EqAffine::generator());
+        assert_eq!(transcript.read_scalar().unwrap(), Fp::from(2u64));
+        assert_eq!(transcript.squeeze_challenge().get_scalar(), first);
+        assert_eq!(transcript.read_point().unwrap(), EqAffine::identity());
+        assert_eq!(transcript.squeeze_challenge().get_scalar(), second);
+
+        // a tampered scalar, following the first point, leads to other challenges
+        let mut tampered = proof.clone();
+        tampered[32] ^= 1;
+        let mut transcript =
+            PoseidonRead::<_, EqAffine, T, RATE, R_F, R_P>::init(tampered.as_slice());
+        transcript.common_scalar(Fp::from(1u64)).unwrap();
+        transcript.read_point().unwrap();
+        assert_eq!(transcript.read_scalar().unwrap(), Fp::from(3u64));
+        assert_ne!(transcript.squeeze_challenge().get_scalar(), first);
+    }
+
+    #[test]
+    fn test_poseidon_transcript_sbox() {
+        let spec = PoseidonSpec::<Fp, T, RATE>::new(R_F, R_P).with_sbox(SBox::Pow7);
+        let mut transcript =
+            PoseidonWrite::<_, EqAffine, T, RATE, R_F, R_P>::new(Vec::new(), spec.clone());
+        transcript.write_scalar(Fp::from(2u64)).unwrap();
+        let challenge = transcript.squeeze_challenge().get_scalar();
+        let proof = transcript.finalize();
+
+        let mut hasher = PoseidonHash::<EqAffine, Fp, T, RATE>::new(spec.clone());
+        assert_eq!(challenge, hasher.absorb_scalar(Fp::from(2u64)).squeeze());
+
+        let mut transcript =
+            PoseidonRead::<_, EqAffine, T, RATE, R_F, R_P>::new(proof.as_slice(), spec);
+        assert_eq!(transcript.read_scalar().unwrap(), Fp::from(2u64));
+        assert_eq!(transcript.squeeze_challenge().get_scalar(), challenge);
+
+        // the default transcript, with `x^5`, leads to another challenge
+        let mut transcript = PoseidonRead::<_, EqAffine, T, RATE, R_F, R_P>::init(proof.as_slice());
+        transcript.read_scalar().unwrap();
+        assert_ne!(transcript.squeeze_challenge().get_scalar(), challenge);
+    }
+
+    #[test]
+    #[should_panic(expected = "the spec has 8 full and 60 partial rounds instead of 8 and 57")]
+    fn test_poseidon_transcript_rounds() {
+        PoseidonRead::<_, EqAffine, T, RATE, R_F, R_P>::new(
+            std::io::empty(),
+            PoseidonSpec::new(R_F, 60),
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use std::{marker::PhantomData, slice};
+
+use ff::FromUniformBytes;
+use halo2_proofs::{
+    arithmetic::CurveAffine,
+    circuit::{Chip, Value},
+    plonk::Error,
+};
+
+use crate::{
+    main_gate::{AssignedValue, MainGate, RegionCtx},
+    poseidon_circuit::{PoseidonChip, PoseidonConfig},
+    poseidon_hash::PoseidonSpec,
+    ro_types::{AssignedBasePoint, ROCircuitTrait},
+};
+
+/// In-circuit replay of [`crate::transcript::PoseidonRead`]: the elements of the proof are
+/// assigned and absorbed in the same order, and the challenges are the squeezed elements,
+/// i.e. the scalars of the native [`halo2_proofs::transcript::Challenge255`]
+///
+/// As for [`PoseidonChip`], a chip mutated inside `Layouter::assign_region` should be created
+/// within the closure.
+pub struct TranscriptChip<C: CurveAffine, const T: usize, const RATE: usize>
+where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    main_gate: MainGate<C::ScalarExt, T>,
+    poseidon: PoseidonChip<C::ScalarExt, T, RATE>,
+    _marker: PhantomData<C>,
+}
+
+impl<C: CurveAffine, const T: usize, const RATE: usize> TranscriptChip<C, T, RATE>
+where
+    C::ScalarExt: FromUniformBytes<64>,
+{
+    pub fn new(
+        config: impl Into<PoseidonConfig<T>>,
+        spec: PoseidonSpec<C::ScalarExt, T, RATE>,
+    ) -> Self {
+        let config = config.into();
+        let main_gate = match &config {
+            PoseidonConfig::MainGate(config) | PoseidonConfig::RoundGate(config, _) => {
+                MainGate::new(config.clone())
+            }
+        };
+        Self {
+            main_gate,
+            poseidon: PoseidonChip::new(config, spec),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Assigns a point of the proof and absorbs it, see [`AssignedBasePoint::assign`]
+    ///
+    /// The point is not constrained to be on the curve: its coordinates are limbs of the base
+    /// field of `C`, and `y^2 = x^3 + a * x + b` over them needs non-native arithmetic, which
+    /// `MainGate` does not provide. The caller has to check it, e.g. where the recursive
+    /// verifier operates on the point.
+    pub fn read_point_unchecked(
+        &mut self,
+        ctx: &mut RegionCtx<'_, C::ScalarExt>,
+        point: Value<C>,
+    ) -> Result<AssignedBasePoint<C::ScalarExt>, Error> {
+        let point = AssignedBasePoint::assign(&self.main_gate, ctx, point)?;
+        self.common_point(&point);
+        Ok(point)
+    }
+
+    /// Assigns a scalar of the proof and absorbs it
+    pub fn read_scalar(
+        &mut self,
+        ctx: &mut RegionCtx<'_, C::ScalarExt>,
+        scalar: Value<C::ScalarExt>,
+    ) -> Result<AssignedValue<C::ScalarExt>, Error> {
+        let scalar = ctx.assign_advice(
+            || "transcript: scalar",
+            self.main_gate.config().input,
+            scalar,
+        )?;
+        ctx.next();
+        self.common_scalar(&scalar);
+        Ok(scalar)
+    }
+
+    pub fn common_point(&mut self, point: &AssignedBasePoint<C::ScalarExt>) {
+        ROCircuitTrait::<C>::absorb_point(&mut self.poseidon, point);
+    }
+
+    pub fn common_scalar(&mut self, scalar: &AssignedValue<C::ScalarExt>) {
+        self.poseidon.update_assigned(slice::from_ref(scalar));
+    }
+
+    pub fn squeeze_challenge(
+        &mut self,
+        ctx: &mut RegionCtx<'_, C::ScalarExt>,
+    ) -> Result<AssignedValue<C::ScalarExt>, Error> {
+        self.poseidon.squeeze(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        dev::MockProver,
+        plonk::{Circuit, Column, ConstraintSystem, Instance},
+        transcript::{
+            EncodedChallenge, Transcript, TranscriptRead, TranscriptWrite, TranscriptWriterBuffer,
+        },
+    };
+    use halo2curves::{
+        group::prime::PrimeCurveAffine,
+        pasta::{EqAffine, Fp},
+    };
+
+    use super::*;
+    use crate::{
+        main_gate::MainGateConfig,
+        transcript::{PoseidonRead, PoseidonWrite},
+    };
+
+    const T: usize = 3;
+    const RATE: usize = 2;
+    const R_F: usize = 4;
+    const R_P: usize = 3;
+
+    #[derive(Clone, Debug)]
+    struct TranscriptCircuitConfig {
+        pconfig: MainGateConfig<T>,
+        instance: Column<Instance>,
+    }
+
+    // replays a transcript of a public scalar, read from instance row 2, followed by a point,
+    // a scalar, a challenge, a point and a challenge, the challenges being exposed at rows 0
+    // and 1
+    struct TranscriptCircuit {
+        points: [Value<EqAffine>; 2],
+        scalar: Value<Fp>,
+    }
+
+    impl Circuit<Fp> for TranscriptCircuit {
+        type Config = TranscriptCircuitConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                points: [Value::unknown(); 2],
+                scalar: Value::unknown(),
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let instance = meta.instance_column();
+            meta.enable_equality(instance);
+            let mut adv_cols = [(); T + 2].map(|_| meta.advice_column()).into_iter();
+            let mut fix_cols = [(); 2 * T + 4].map(|_| meta.fixed_column()).into_iter();
+            let pconfig = MainGate::configure(meta, &mut adv_cols, &mut fix_cols);
+            Self::Config { pconfig, instance }
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+            let challenges = layouter.assign_region(
+                || "transcript",
+                |region| {
+                    let ctx = &mut RegionCtx::new(region, 0);
+                    let mut transcript = TranscriptChip::<EqAffine, T, RATE>::new(
+                        config.pconfig.clone(),
+                        spec.clone(),
+                    );
+                    let public = ctx.region.assign_advice_from_instance(
+                        || "public scalar",
+                        config.instance,
+                        2,
+                        config.pconfig.input,
+                        ctx.offset(),
+                    )?;
+                    ctx.next();
+                    transcript.common_scalar(&public);
+                    transcript.read_point_unchecked(ctx, self.points[0])?;
+                    transcript.read_scalar(ctx, self.scalar)?;
+                    let first = transcript.squeeze_challenge(ctx)?;
+                    transcript.read_point_unchecked(ctx, self.points[1])?;
+                    let second = transcript.squeeze_challenge(ctx)?;
+                    Ok([first, second])
+                },
+            )?;
+            for (row, challenge) in challenges.iter().enumerate() {
+                layouter.constrain_instance(challenge.cell(), config.instance, row)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_transcript() {
+        const K: u32 = 13;
+        // the toy rounds of the circuit are not accepted by `init`
+        let spec = PoseidonSpec::<Fp, T, RATE>::new_unchecked(R_F, R_P);
+        let mut transcript =
+            PoseidonWrite::<_, EqAffine, T, RATE, R_F, R_P>::new(Vec::new(), spec.clone());
+        transcript.common_scalar(Fp::from(1u64)).unwrap();
+        transcript.write_point(EqAffine::generator()).unwrap();
+        transcript.write_scalar(Fp::from(2u64)).unwrap();
+        transcript.squeeze_challenge();
+        transcript.write_point(EqAffine::identity()).unwrap();
+        let proof = transcript.finalize();
+
+        let mut transcript =
+            PoseidonRead::<_, EqAffine, T, RATE, R_F, R_P>::new(proof.as_slice(), spec);
+        transcript.common_scalar(Fp::from(1u64)).unwrap();
+        let first_point = transcript.read_point().unwrap();
+        let scalar = transcript.read_scalar().unwrap();
+        let first = transcript.squeeze_challenge().get_scalar();
+        let second_point = transcript.read_point().unwrap();
+        let second = transcript.squeeze_challenge().get_scalar();
+
+        let circuit = TranscriptCircuit {
+            points: [Value::known(first_point), Value::known(second_point)],
+            scalar: Value::known(scalar),
+        };
+        let public = Fp::from(1u64);
+        let prover = MockProver::run(K, &circuit, vec![vec![first, second, public]]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // the challenges depend on the public scalar
+        let prover = MockProver::run(
+            K,
+            &circuit,
+            vec![vec![first, second, public + Fp::from(1u64)]],
+        )
+        .unwrap();
+        assert!(prover.verify().is_err());
+
+        let circuit = TranscriptCircuit {
+            points: [Value::known(first_point), Value::known(second_point)],
+            scalar: Value::known(scalar + Fp::from(1u64)),
+        };
+        let prover = MockProver::run(K, &circuit, vec![vec![first, second, public]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}